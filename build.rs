@@ -0,0 +1,35 @@
+// with the `embedded-helper` feature, compiles the managed helper in
+// helper/ (or takes a prebuilt one from CORECLR_HELPER_DLL) so that
+// src/helper.rs can include it.
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    if env::var_os("CARGO_FEATURE_EMBEDDED_HELPER").is_none() {
+        return;
+    }
+
+    println!("cargo:rerun-if-env-changed=CORECLR_HELPER_DLL");
+    println!("cargo:rerun-if-changed=helper/CoreClrHelper.csproj");
+    println!("cargo:rerun-if-changed=helper/Diagnostics.cs");
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let dll = match env::var_os("CORECLR_HELPER_DLL") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let build_dir = out_dir.join("helper");
+            let status = Command::new("dotnet")
+                .args(["build", "helper/CoreClrHelper.csproj", "-c", "Release", "-o"])
+                .arg(&build_dir)
+                .status()
+                .expect("the embedded-helper feature needs the dotnet SDK, or CORECLR_HELPER_DLL set to a prebuilt helper");
+            assert!(status.success(), "building helper/ failed");
+            build_dir.join("CoreClrHelper.dll")
+        }
+    };
+
+    fs::copy(&dll, out_dir.join("CoreClrHelper.dll"))
+        .unwrap_or_else(|err| panic!("could not copy {}: {}", dll.display(), err));
+}
@@ -0,0 +1,134 @@
+using System;
+using System.Collections.Generic;
+using System.Linq;
+using System.Reflection;
+using System.Runtime;
+using System.Runtime.InteropServices;
+using System.Runtime.Loader;
+
+namespace CoreClrHelper
+{
+    // Strings are UTF-8 both ways, and returned ones are allocated with
+    // Marshal.StringToCoTaskMemUTF8 for the host to hand back to Free.
+    // Failures are reported as a string starting with "error: ", since
+    // exceptions can't cross an UnmanagedCallersOnly boundary.
+    public static class Diagnostics
+    {
+        [UnmanagedCallersOnly]
+        public static IntPtr ListUnmanagedCallers(IntPtr assemblyName)
+        {
+            string result;
+            try
+            {
+                var assembly = Assembly.Load(new AssemblyName(Marshal.PtrToStringUTF8(assemblyName)));
+                var names = LoadableTypes(assembly)
+                    .SelectMany(type => type.GetMethods(BindingFlags.Public | BindingFlags.NonPublic | BindingFlags.Static | BindingFlags.DeclaredOnly)
+                        .Where(method => method.GetCustomAttribute<UnmanagedCallersOnlyAttribute>() != null)
+                        .Select(method => type.FullName + "::" + method.Name));
+                result = string.Join("\n", names);
+            }
+            catch (Exception e)
+            {
+                result = "error: " + e.Message;
+            }
+
+            return Marshal.StringToCoTaskMemUTF8(result);
+        }
+
+        [UnmanagedCallersOnly]
+        public static unsafe void GetGcConfig(int* isServer, int* latencyMode)
+        {
+            *isServer = GCSettings.IsServerGC ? 1 : 0;
+            *latencyMode = (int)GCSettings.LatencyMode;
+        }
+
+        // Returns a native-callable pointer to the [UnmanagedCallersOnly]
+        // static method with the given metadata tokens, or zero.
+        [UnmanagedCallersOnly]
+        public static IntPtr ResolveMethodToken(IntPtr assemblyName, int typeToken, int methodToken)
+        {
+            try
+            {
+                var module = Assembly.Load(new AssemblyName(Marshal.PtrToStringUTF8(assemblyName))).ManifestModule;
+                var type = module.ResolveType(typeToken);
+                var method = module.ResolveMethod(methodToken) as MethodInfo;
+                if (method == null
+                    || method.DeclaringType != type
+                    || !method.IsStatic
+                    || method.GetCustomAttribute<UnmanagedCallersOnlyAttribute>() == null)
+                {
+                    return IntPtr.Zero;
+                }
+
+                return method.MethodHandle.GetFunctionPointer();
+            }
+            catch (Exception)
+            {
+                return IntPtr.Zero;
+            }
+        }
+
+        // Returns a native-callable pointer to the named method if it's a
+        // static [UnmanagedCallersOnly] one taking nothing and returning
+        // void, or zero, so the host can call it without trusting the name.
+        [UnmanagedCallersOnly]
+        public static IntPtr ResolveVoidMethod(IntPtr assemblyName, IntPtr typeName, IntPtr methodName)
+        {
+            try
+            {
+                var assembly = Assembly.Load(new AssemblyName(Marshal.PtrToStringUTF8(assemblyName)));
+                var type = assembly.GetType(Marshal.PtrToStringUTF8(typeName));
+                var method = type?.GetMethod(Marshal.PtrToStringUTF8(methodName),
+                    BindingFlags.Public | BindingFlags.NonPublic | BindingFlags.Static | BindingFlags.DeclaredOnly,
+                    null, Type.EmptyTypes, null);
+                if (method == null
+                    || method.ReturnType != typeof(void)
+                    || method.IsGenericMethodDefinition
+                    || method.GetCustomAttribute<UnmanagedCallersOnlyAttribute>() == null)
+                {
+                    return IntPtr.Zero;
+                }
+
+                return method.MethodHandle.GetFunctionPointer();
+            }
+            catch (Exception)
+            {
+                return IntPtr.Zero;
+            }
+        }
+
+        // Loads the assembly at the given path into the default load
+        // context, where the host can then find it by name, and returns
+        // that name.
+        [UnmanagedCallersOnly]
+        public static IntPtr LoadAssemblyFromPath(IntPtr path)
+        {
+            string result;
+            try
+            {
+                result = AssemblyLoadContext.Default.LoadFromAssemblyPath(Marshal.PtrToStringUTF8(path)).GetName().Name;
+            }
+            catch (Exception e)
+            {
+                result = "error: " + e.Message;
+            }
+
+            return Marshal.StringToCoTaskMemUTF8(result);
+        }
+
+        [UnmanagedCallersOnly]
+        public static void Free(IntPtr str) => Marshal.FreeCoTaskMem(str);
+
+        private static IEnumerable<Type> LoadableTypes(Assembly assembly)
+        {
+            try
+            {
+                return assembly.GetTypes();
+            }
+            catch (ReflectionTypeLoadException e)
+            {
+                return e.Types.Where(type => type != null);
+            }
+        }
+    }
+}
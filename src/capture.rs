@@ -0,0 +1,355 @@
+//! Redirection of the process's stdout and stderr into pipes, so that
+//! output written by managed code can be handed back to the caller.
+//!
+//! The managed Console duplicates the standard handles the first time it
+//! is used, so the write side of a pipe stays open for as long as the
+//! runtime lives and we can never wait for EOF. Instead, a reader thread
+//! keeps each pipe from filling up, and `take` drains whatever is left
+//! without blocking. Both read under the buffer's lock, so bytes can't
+//! be reordered between them.
+use std::io::{self, Write};
+use std::panic::RefUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+
+pub struct OutputCapture {
+    stdout: CapturedStream,
+    stderr: CapturedStream,
+}
+
+// the only state shared with the reader threads sits behind a mutex, so
+// observing an OutputCapture after a panic can't see a broken invariant.
+impl RefUnwindSafe for OutputCapture {}
+
+impl OutputCapture {
+    /// Starts redirecting stdout and stderr. They are restored when the
+    /// returned value is dropped.
+    pub fn start() -> io::Result<OutputCapture> {
+        // anything Rust has buffered belongs on the real stdout.
+        let _ = io::stdout().flush();
+        let stdout = CapturedStream::start(sys::Stream::Stdout)?;
+        let stderr = CapturedStream::start(sys::Stream::Stderr)?;
+        Ok(OutputCapture {
+            stdout,
+            stderr
+        })
+    }
+
+    /// Returns everything written to stdout and stderr since the last call.
+    pub fn take(&self) -> (Vec<u8>, Vec<u8>) {
+        let _ = io::stdout().flush();
+        (self.stdout.take(), self.stderr.take())
+    }
+}
+
+struct CapturedStream {
+    pipe: Arc<sys::Pipe>,
+    buffer: Arc<Mutex<Vec<u8>>>,
+    done: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl CapturedStream {
+    fn start(stream: sys::Stream) -> io::Result<CapturedStream> {
+        let pipe = Arc::new(sys::Pipe::redirect(stream)?);
+        let buffer = Arc::new(Mutex::new(vec![]));
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let pipe = pipe.clone();
+            let buffer = buffer.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    if pipe.wait_readable() {
+                        let mut buffer = buffer.lock().unwrap();
+                        pipe.drain(&mut buffer);
+                    }
+                }
+            })
+        };
+
+        Ok(CapturedStream {
+            pipe,
+            buffer,
+            done,
+            reader: Some(reader)
+        })
+    }
+
+    fn take(&self) -> Vec<u8> {
+        let mut buffer = self.buffer.lock().unwrap();
+        self.pipe.drain(&mut buffer);
+        ::std::mem::take(&mut *buffer)
+    }
+}
+
+impl Drop for CapturedStream {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        self.pipe.restore();
+        self.done.store(true, Ordering::SeqCst);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use libc;
+    use std::io;
+
+    pub enum Stream {
+        Stdout,
+        Stderr
+    }
+
+    pub struct Pipe {
+        fd: libc::c_int,
+        saved: libc::c_int,
+        read: libc::c_int,
+    }
+
+    impl Pipe {
+        pub fn redirect(stream: Stream) -> io::Result<Pipe> {
+            let fd = match stream {
+                Stream::Stdout => libc::STDOUT_FILENO,
+                Stream::Stderr => libc::STDERR_FILENO
+            };
+
+            unsafe {
+                let mut fds = [0; 2];
+                if libc::pipe(fds.as_mut_ptr()) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                let flags = libc::fcntl(fds[0], libc::F_GETFL);
+                libc::fcntl(fds[0], libc::F_SETFL, flags | libc::O_NONBLOCK);
+                let saved = libc::dup(fd);
+                if saved < 0 || libc::dup2(fds[1], fd) < 0 {
+                    let err = io::Error::last_os_error();
+                    libc::close(fds[0]);
+                    libc::close(fds[1]);
+                    if saved >= 0 {
+                        libc::close(saved);
+                    }
+
+                    return Err(err);
+                }
+
+                // the redirected fd now holds the only write end we need.
+                libc::close(fds[1]);
+                Ok(Pipe {
+                    fd,
+                    saved,
+                    read: fds[0]
+                })
+            }
+        }
+
+        pub fn wait_readable(&self) -> bool {
+            let mut pollfd = libc::pollfd {
+                fd: self.read,
+                events: libc::POLLIN,
+                revents: 0
+            };
+
+            unsafe { libc::poll(&mut pollfd as *mut _, 1, 50) > 0 }
+        }
+
+        pub fn drain(&self, buffer: &mut Vec<u8>) {
+            let mut chunk = [0u8; 4096];
+            loop {
+                let n = unsafe {
+                    libc::read(self.read, chunk.as_mut_ptr() as *mut libc::c_void, chunk.len())
+                };
+
+                if n <= 0 {
+                    break;
+                }
+
+                buffer.extend_from_slice(&chunk[..n as usize]);
+            }
+        }
+
+        pub fn restore(&self) {
+            unsafe {
+                libc::dup2(self.saved, self.fd);
+            }
+        }
+    }
+
+    impl Drop for Pipe {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.saved);
+                libc::close(self.read);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use libc;
+    use std::io;
+    use std::ptr;
+    use std::thread;
+    use std::time::Duration;
+
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreatePipe(read: *mut *mut libc::c_void,
+            write: *mut *mut libc::c_void,
+            attributes: *mut libc::c_void,
+            size: u32) -> libc::c_int;
+        fn GetStdHandle(std_handle: u32) -> *mut libc::c_void;
+        fn SetStdHandle(std_handle: u32, handle: *mut libc::c_void) -> libc::c_int;
+        fn PeekNamedPipe(pipe: *mut libc::c_void,
+            buffer: *mut libc::c_void,
+            buffer_size: u32,
+            bytes_read: *mut u32,
+            total_bytes_avail: *mut u32,
+            bytes_left_this_message: *mut u32) -> libc::c_int;
+        fn ReadFile(file: *mut libc::c_void,
+            buffer: *mut libc::c_void,
+            bytes_to_read: u32,
+            bytes_read: *mut u32,
+            overlapped: *mut libc::c_void) -> libc::c_int;
+        fn CloseHandle(handle: *mut libc::c_void) -> libc::c_int;
+    }
+
+    pub enum Stream {
+        Stdout,
+        Stderr
+    }
+
+    pub struct Pipe {
+        std_handle: u32,
+        saved: *mut libc::c_void,
+        read: *mut libc::c_void,
+        write: *mut libc::c_void,
+    }
+
+    // the handles are only ever used through the kernel, which is fine
+    // with them being used from any thread.
+    unsafe impl Send for Pipe {}
+    unsafe impl Sync for Pipe {}
+
+    impl Pipe {
+        pub fn redirect(stream: Stream) -> io::Result<Pipe> {
+            let std_handle = match stream {
+                Stream::Stdout => STD_OUTPUT_HANDLE,
+                Stream::Stderr => STD_ERROR_HANDLE
+            };
+
+            unsafe {
+                let mut read = ptr::null_mut();
+                let mut write = ptr::null_mut();
+                if CreatePipe(&mut read, &mut write, ptr::null_mut(), 0) == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                let saved = GetStdHandle(std_handle);
+                if SetStdHandle(std_handle, write) == 0 {
+                    let err = io::Error::last_os_error();
+                    CloseHandle(read);
+                    CloseHandle(write);
+                    return Err(err);
+                }
+
+                Ok(Pipe {
+                    std_handle,
+                    saved,
+                    read,
+                    write
+                })
+            }
+        }
+
+        fn available(&self) -> u32 {
+            let mut avail = 0;
+            let ok = unsafe {
+                PeekNamedPipe(self.read, ptr::null_mut(), 0, ptr::null_mut(), &mut avail, ptr::null_mut())
+            };
+
+            if ok == 0 { 0 } else { avail }
+        }
+
+        pub fn wait_readable(&self) -> bool {
+            if self.available() > 0 {
+                true
+            } else {
+                thread::sleep(Duration::from_millis(50));
+                false
+            }
+        }
+
+        pub fn drain(&self, buffer: &mut Vec<u8>) {
+            let mut chunk = [0u8; 4096];
+            loop {
+                let avail = self.available();
+                if avail == 0 {
+                    break;
+                }
+
+                let mut n = 0;
+                let to_read = ::std::cmp::min(avail, chunk.len() as u32);
+                let ok = unsafe {
+                    ReadFile(self.read, chunk.as_mut_ptr() as *mut _, to_read, &mut n, ptr::null_mut())
+                };
+
+                if ok == 0 || n == 0 {
+                    break;
+                }
+
+                buffer.extend_from_slice(&chunk[..n as usize]);
+            }
+        }
+
+        pub fn restore(&self) {
+            unsafe {
+                SetStdHandle(self.std_handle, self.saved);
+            }
+        }
+    }
+
+    impl Drop for Pipe {
+        fn drop(&mut self) {
+            unsafe {
+                CloseHandle(self.write);
+                CloseHandle(self.read);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use std::io::{self, Error, ErrorKind};
+
+    pub enum Stream {
+        Stdout,
+        Stderr
+    }
+
+    pub struct Pipe;
+
+    impl Pipe {
+        pub fn redirect(_stream: Stream) -> io::Result<Pipe> {
+            Err(Error::new(ErrorKind::Unsupported, "output capture is not supported on this target"))
+        }
+
+        pub fn wait_readable(&self) -> bool {
+            false
+        }
+
+        pub fn drain(&self, _buffer: &mut Vec<u8>) {}
+
+        pub fn restore(&self) {}
+    }
+}
@@ -0,0 +1,149 @@
+//! Locating a machine-wide installation of the .NET runtime.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The dotnet roots that are searched, in order. `DOTNET_ROOT` is
+/// honored first since it's how non-standard installs advertise themselves.
+fn dotnet_roots() -> Vec<PathBuf> {
+    let mut roots = vec![];
+    if let Some(root) = env::var_os("DOTNET_ROOT") {
+        roots.push(PathBuf::from(root));
+    }
+
+    if cfg!(windows) {
+        if let Some(program_files) = env::var_os("ProgramFiles") {
+            roots.push(Path::new(&program_files).join("dotnet"));
+        }
+    } else if cfg!(target_os = "macos") {
+        roots.push(PathBuf::from("/usr/local/share/dotnet"));
+    } else {
+        roots.push(PathBuf::from("/usr/share/dotnet"));
+        roots.push(PathBuf::from("/usr/lib/dotnet"));
+    }
+
+    roots
+}
+
+/// Finds the directory of the highest installed runtime whose version
+/// starts with `version` (or the highest overall if `version` is None).
+/// On failure, returns a message naming every directory that was searched.
+pub fn find_runtime(version: Option<&str>) -> Result<PathBuf, String> {
+    let mut best : Option<(Vec<u64>, PathBuf)> = None;
+    let mut searched = vec![];
+    for root in dotnet_roots() {
+        // one subdirectory per installed runtime version lives under here.
+        let dir = root.join("shared").join("Microsoft.NETCore.App");
+        searched.push(dir.display().to_string());
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue
+            };
+
+            if let Some(v) = version {
+                if !version_matches(&name, v) {
+                    continue;
+                }
+            }
+
+            let parsed = parse_version(&name);
+            let is_better = match best {
+                Some((ref best_version, _)) => parsed > *best_version,
+                None => true
+            };
+
+            if is_better {
+                best = Some((parsed, entry.path()));
+            }
+        }
+    }
+
+    match best {
+        Some((_, path)) => Ok(path),
+        None => {
+            let wanted = match version {
+                Some(v) => format!("version {} of the .NET runtime", v),
+                None => "a .NET runtime".to_string()
+            };
+
+            Err(format!("could not find {}; searched {}", wanted, searched.join(", ")))
+        }
+    }
+}
+
+/// Finds the newest `hostfxr` library under `dotnet_root`, or under the
+/// usual dotnet roots if that's None. On failure, returns a message naming
+/// every directory that was searched.
+#[cfg(all(feature = "hostfxr", any(unix, windows)))]
+pub fn find_hostfxr(dotnet_root: Option<&Path>) -> Result<PathBuf, String> {
+    let roots = match dotnet_root {
+        Some(root) => vec![root.to_path_buf()],
+        None => dotnet_roots()
+    };
+
+    let library = if cfg!(windows) {
+        "hostfxr.dll"
+    } else if cfg!(target_os = "macos") {
+        "libhostfxr.dylib"
+    } else {
+        "libhostfxr.so"
+    };
+
+    let mut best : Option<(Vec<u64>, PathBuf)> = None;
+    let mut searched = vec![];
+    for root in roots {
+        // one subdirectory per installed host version lives under here.
+        let dir = root.join("host").join("fxr");
+        searched.push(dir.display().to_string());
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path().join(library);
+            if !path.is_file() {
+                continue;
+            }
+
+            let parsed = parse_version(&entry.file_name().to_string_lossy());
+            let is_better = match best {
+                Some((ref best_version, _)) => parsed > *best_version,
+                None => true
+            };
+
+            if is_better {
+                best = Some((parsed, path));
+            }
+        }
+    }
+
+    match best {
+        Some((_, path)) => Ok(path),
+        None => Err(format!("could not find {}; searched {}", library, searched.join(", ")))
+    }
+}
+
+/// "6.0" matches "6.0.25" but not "6.01.0".
+fn version_matches(candidate: &str, wanted: &str) -> bool {
+    candidate == wanted
+        || (candidate.starts_with(wanted) && candidate[wanted.len()..].starts_with(['.', '-']))
+}
+
+/// Parses the numeric components of a version like "8.0.1-preview.2",
+/// ignoring anything that isn't a leading run of digits.
+fn parse_version(version: &str) -> Vec<u64> {
+    let release = version.split('-').next().unwrap_or("");
+    release.split('.')
+        .map(|part| {
+            let digits : String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+            digits.parse().unwrap_or(0)
+        })
+        .collect()
+}
@@ -0,0 +1,126 @@
+//! The error type returned by the hosting API.
+use std::error;
+use std::ffi::NulError;
+use std::fmt;
+use std::io::{self, Error, ErrorKind};
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum ClrError {
+    /// The coreclr library could not be loaded.
+    LibraryLoad(io::Error),
+    /// The loaded library doesn't export a required hosting function.
+    SymbolNotFound(String),
+    /// `coreclr_initialize` failed with this HRESULT.
+    Initialize(i32),
+    /// `coreclr_create_delegate` failed with this HRESULT.
+    CreateDelegate(i32),
+    /// `coreclr_execute_assembly` failed with this HRESULT.
+    Execute(i32),
+    /// `coreclr_shutdown` failed with this HRESULT.
+    Shutdown(i32),
+    /// Managed code crashed while executing an assembly. Holds the HRESULT
+    /// or exit code and the text the runtime reported for the crash,
+    /// usually the exception and its stack trace.
+    UnhandledException(i32, String),
+    /// A path that has to be handed to the runtime isn't valid UTF-8.
+    NonUtf8Path(PathBuf),
+    /// Anything else, such as a missing file or an invalid configuration.
+    Io(io::Error),
+}
+
+impl ClrError {
+    /// The closest io::ErrorKind for this error.
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            ClrError::LibraryLoad(ref err) | ClrError::Io(ref err) => err.kind(),
+            ClrError::SymbolNotFound(_) => ErrorKind::NotFound,
+            ClrError::Initialize(hr)
+                | ClrError::CreateDelegate(hr)
+                | ClrError::Execute(hr)
+                | ClrError::Shutdown(hr)
+                | ClrError::UnhandledException(hr, _) => hresult_to_error(hr).kind(),
+            ClrError::NonUtf8Path(_) => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl fmt::Display for ClrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClrError::LibraryLoad(ref err) => write!(f, "failed to load coreclr: {}", err),
+            ClrError::SymbolNotFound(ref name) => write!(f, "coreclr does not export {}", name),
+            ClrError::Initialize(hr) => write!(f, "coreclr_initialize failed: {}", hresult_to_error(hr)),
+            ClrError::CreateDelegate(hr) => write!(f, "coreclr_create_delegate failed: {}", hresult_to_error(hr)),
+            ClrError::Execute(hr) => write!(f, "coreclr_execute_assembly failed: {}", hresult_to_error(hr)),
+            ClrError::Shutdown(hr) => write!(f, "coreclr_shutdown failed: {}", hresult_to_error(hr)),
+            ClrError::UnhandledException(hr, ref text) =>
+                write!(f, "managed code crashed ({}):\n{}", hresult_to_error(hr), text.trim_end()),
+            ClrError::NonUtf8Path(ref path) => write!(f, "path is not valid UTF-8: {}", path.display()),
+            ClrError::Io(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for ClrError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ClrError::LibraryLoad(ref err) | ClrError::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for ClrError {
+    fn from(err: io::Error) -> ClrError {
+        ClrError::Io(err)
+    }
+}
+
+impl From<NulError> for ClrError {
+    fn from(err: NulError) -> ClrError {
+        ClrError::Io(err.into())
+    }
+}
+
+impl From<ClrError> for io::Error {
+    fn from(err: ClrError) -> io::Error {
+        match err {
+            ClrError::Io(err) => err,
+            other => Error::new(other.kind(), other.to_string())
+        }
+    }
+}
+
+/// Returned when a second runtime is started in the same process.
+pub const ALREADY_INITIALIZED: &str =
+    "CoreCLR is already initialized in this process; only one ClrHost may exist at a time";
+
+/// Returned when a runtime is started after an earlier one was shut down.
+pub const SHUT_DOWN: &str = "CoreCLR cannot be re-initialized in a process after shutdown";
+
+/// Translates a failing HRESULT returned by one of the coreclr hosting
+/// functions into an io::Error with a readable message. These are not
+/// errno values, so `Error::from_raw_os_error` produces garbage for them.
+pub fn hresult_to_error(hr: i32) -> io::Error {
+    let code = hr as u32;
+    let (kind, message) = match code {
+        0x80070002 => (ErrorKind::NotFound, "file not found"),
+        0x80070003 => (ErrorKind::NotFound, "path not found"),
+        0x8007000B => (ErrorKind::InvalidData, "bad image format"),
+        0x8007000E => (ErrorKind::Other, "out of memory"),
+        0x80070057 => (ErrorKind::InvalidInput, "invalid argument"),
+        0x80004005 => (ErrorKind::Other, "unspecified failure"),
+        0x80131040 => (ErrorKind::InvalidData, "assembly manifest does not match the assembly reference"),
+        0x80131500 => (ErrorKind::Other, "generic runtime host failure"),
+        0x80131015 => (ErrorKind::AlreadyExists, ALREADY_INITIALIZED),
+        0x80131509 => (ErrorKind::Other, "invalid operation"),
+        0x80131513 => (ErrorKind::NotFound, "missing method"),
+        0x80131522 => (ErrorKind::NotFound, "type load failure"),
+        0x80131534 => (ErrorKind::Other, "type initializer threw an exception"),
+        0xE0434352 => (ErrorKind::Other, "unhandled managed exception"),
+        _ => return Error::other(format!("CLR error 0x{:08X}", code)),
+    };
+
+    Error::new(kind, format!("{} (CLR error 0x{:08X})", message, code))
+}
@@ -0,0 +1,107 @@
+//! Names for the managed helper assembly built from `helper/`, which
+//! implements the diagnostics that need reflection on the managed side.
+#[cfg(unix)]
+use libc;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process;
+
+pub const ASSEMBLY: &str = "CoreClrHelper";
+pub const DIAGNOSTICS_TYPE: &str = "CoreClrHelper.Diagnostics";
+pub const FREE_METHOD: &str = "Free";
+
+/// Prefix the helper puts on a result to signal failure instead.
+pub const ERROR_PREFIX: &str = "error: ";
+
+#[cfg(feature = "embedded-helper")]
+static EMBEDDED: Option<&[u8]> = Some(include_bytes!(concat!(env!("OUT_DIR"), "/CoreClrHelper.dll")));
+
+#[cfg(not(feature = "embedded-helper"))]
+static EMBEDDED: Option<&[u8]> = None;
+
+/// The error returned when the helper can't be reached.
+pub fn unavailable(cause: &dyn fmt::Display) -> Error {
+    Error::new(ErrorKind::Unsupported,
+        format!("the {} helper assembly could not be loaded ({}); build helper/ and add its output \
+            directory to the assembly probe path, or enable the embedded-helper feature", ASSEMBLY, cause))
+}
+
+/// Where the helper compiled into the crate, if there is one, is put for
+/// the runtime to load. Nothing is written there until `write_embedded`.
+/// The directory is named after the user and the contents, so each user
+/// has one copy per build, shared between their processes.
+pub fn embedded_path() -> Option<PathBuf> {
+    let bytes = EMBEDDED?;
+    let dir = env::temp_dir().join(format!("coreclr-rs-helper-{}{:016x}", user_prefix(), fnv1a(bytes)));
+    Some(dir.join(format!("{}.dll", ASSEMBLY)))
+}
+
+/// Writes the helper compiled into the crate, if there is one, to
+/// `embedded_path`, unless an identical copy is already there.
+pub fn write_embedded() -> io::Result<()> {
+    let (bytes, path) = match (EMBEDDED, embedded_path()) {
+        (Some(bytes), Some(path)) => (bytes, path),
+        _ => return Ok(())
+    };
+
+    let dir = path.parent().expect("the helper is always put in a directory");
+    create_private_dir(dir)?;
+    // the runtime trusts whatever it finds on the TPA list, so a copy is
+    // only reused if it's the same all the way through.
+    if fs::read(&path).map(|existing| existing == bytes).unwrap_or(false) {
+        return Ok(());
+    }
+
+    // written under a name of its own first, so nobody can load a
+    // half-written copy.
+    let partial = dir.join(format!("{}.dll.{}", ASSEMBLY, process::id()));
+    fs::write(&partial, bytes)?;
+    fs::rename(&partial, &path)
+}
+
+#[cfg(unix)]
+fn user_prefix() -> String {
+    format!("{}-", unsafe { libc::geteuid() })
+}
+
+// the temp directory is already the user's own everywhere else.
+#[cfg(not(unix))]
+fn user_prefix() -> String {
+    String::new()
+}
+
+// the temp directory is shared, so one already there is only used if
+// nobody but this user could have put anything in it.
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => return Ok(()),
+        Err(ref err) if err.kind() == ErrorKind::AlreadyExists => {}
+        Err(err) => return Err(err)
+    }
+
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.uid() != unsafe { libc::geteuid() } || metadata.mode() & 0o077 != 0 {
+        return Err(Error::new(ErrorKind::PermissionDenied,
+            format!("{} is not a directory only this user can write to, so the {} helper won't be put there",
+                dir.display(), ASSEMBLY)));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+// std's hashers don't promise to stay the same between releases, and the
+// directory name has to.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
@@ -0,0 +1,253 @@
+//! Running an app through hostfxr, the way `dotnet app.dll` does. hostfxr
+//! reads the app's `.runtimeconfig.json` and `.deps.json` to pick a
+//! runtime and build the TPA list, so none of that has to be set up by hand.
+use libc;
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::atomic::Ordering;
+
+use discovery;
+use error::{self, ClrError};
+use loader::DynamicLibrary;
+
+// hostfxr's strings are UTF-16 on Windows and bytes everywhere else.
+#[cfg(unix)]
+type CharT = libc::c_char;
+#[cfg(windows)]
+type CharT = u16;
+
+#[repr(C)]
+struct InitializeParameters {
+    size: libc::size_t,
+    host_path: *const CharT,
+    dotnet_root: *const CharT,
+}
+
+type InitializeForCommandLineFn = extern "C" fn( /* hostfxr_initialize_for_dotnet_command_line */
+    libc::c_int,                                 /* argc */
+    *const *const CharT,                         /* argv */
+    *const InitializeParameters,                 /* parameters */
+    *mut *mut libc::c_void                       /* host_context_handle */
+) -> libc::c_int;
+
+type SetRuntimePropertyValueFn = extern "C" fn( /* hostfxr_set_runtime_property_value */
+    *mut libc::c_void,                           /* host_context_handle */
+    *const CharT,                                /* name */
+    *const CharT                                 /* value */
+) -> libc::c_int;
+
+type RunAppFn = extern "C" fn( /* hostfxr_run_app */
+    *mut libc::c_void                            /* host_context_handle */
+) -> libc::c_int;
+
+type CloseFn = extern "C" fn( /* hostfxr_close */
+    *mut libc::c_void                            /* host_context_handle */
+) -> libc::c_int;
+
+struct HostFxrFunctions {
+    initialize: InitializeForCommandLineFn,
+    set_property: SetRuntimePropertyValueFn,
+    run_app: RunAppFn,
+    close: CloseFn,
+}
+
+/// An app whose runtime has been set up by hostfxr but not yet run. The
+/// host context is closed when this is dropped.
+pub struct HostFxrHost {
+    // kept around so the library stays loaded for as long as we need it.
+    #[allow(dead_code)]
+    hostfxr: DynamicLibrary,
+    functions: HostFxrFunctions,
+    handle: *mut libc::c_void,
+}
+
+impl HostFxrHost {
+    /// Runs the app's entry point and returns its exit code.
+    pub fn run_app(self) -> Result<i32, ClrError> {
+        Ok((self.functions.run_app)(self.handle))
+    }
+}
+
+impl Drop for HostFxrHost {
+    fn drop(&mut self) {
+        (self.functions.close)(self.handle);
+        ::RUNTIME_SHUT_DOWN.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Configures an app to be run through hostfxr. This is the simple path
+/// for regular `dotnet publish` output; `ClrHostBuilder` remains for hosts
+/// that need full control over how coreclr starts.
+#[derive(Clone, Default)]
+pub struct HostFxrBuilder {
+    app: Option<PathBuf>,
+    args: Vec<String>,
+    hostfxr_path: Option<PathBuf>,
+    dotnet_root: Option<PathBuf>,
+    properties: Vec<(String, String)>,
+}
+
+impl HostFxrBuilder {
+    pub fn new() -> HostFxrBuilder {
+        Default::default()
+    }
+
+    /// The app's main assembly, with its `.runtimeconfig.json` next to it.
+    pub fn with_app<T: Into<PathBuf>>(&mut self, app: T) -> &mut HostFxrBuilder {
+        self.app = Some(app.into());
+        self
+    }
+
+    /// Adds a command line argument for the app.
+    pub fn with_arg<T: Into<String>>(&mut self, arg: T) -> &mut HostFxrBuilder {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Loads hostfxr from exactly this file instead of searching for it.
+    pub fn with_hostfxr_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut HostFxrBuilder {
+        self.hostfxr_path = Some(path.into());
+        self
+    }
+
+    /// The dotnet install to search for hostfxr and runtimes. By default,
+    /// `DOTNET_ROOT` and then the platform's usual install locations are
+    /// searched.
+    pub fn with_dotnet_root<T: Into<PathBuf>>(&mut self, root: T) -> &mut HostFxrBuilder {
+        self.dotnet_root = Some(root.into());
+        self
+    }
+
+    /// Sets a runtime property, overriding the runtimeconfig.json.
+    pub fn with_property<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> &mut HostFxrBuilder {
+        self.properties.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn build(&self) -> Result<HostFxrHost, ClrError> {
+        let app = match self.app {
+            Some(ref app) => app,
+            None => return Err(Error::new(ErrorKind::NotFound, "no app provided").into())
+        };
+
+        if !app.is_file() {
+            return Err(Error::new(ErrorKind::NotFound,
+                format!("app {} does not exist", app.display())).into());
+        }
+
+        let hostfxr_path = match self.hostfxr_path {
+            Some(ref path) => path.clone(),
+            None => discovery::find_hostfxr(self.dotnet_root.as_deref())
+                .map_err(|msg| Error::new(ErrorKind::NotFound, msg))?
+        };
+
+        let hostfxr = DynamicLibrary::load(&hostfxr_path).map_err(ClrError::LibraryLoad)?;
+        let functions = unsafe {
+            HostFxrFunctions {
+                initialize: ::resolve(&hostfxr, "hostfxr_initialize_for_dotnet_command_line", None)?,
+                set_property: ::resolve(&hostfxr, "hostfxr_set_runtime_property_value", None)?,
+                run_app: ::resolve(&hostfxr, "hostfxr_run_app", None)?,
+                close: ::resolve(&hostfxr, "hostfxr_close", None)?
+            }
+        };
+
+        // like `dotnet app.dll args...`, the app comes first.
+        let mut argv = vec![host_string(app.as_os_str())?];
+        for arg in &self.args {
+            argv.push(host_string(OsStr::new(arg))?);
+        }
+
+        let argv_ptrs : Vec<_> = argv.iter().map(|a| a.as_ptr()).collect();
+        let dotnet_root = match self.dotnet_root {
+            Some(ref root) => Some(host_string(root.as_os_str())?),
+            None => None
+        };
+
+        let parameters = InitializeParameters {
+            size: mem::size_of::<InitializeParameters>(),
+            host_path: ptr::null(),
+            dotnet_root: dotnet_root.as_ref().map_or(ptr::null(), |r| r.as_ptr())
+        };
+
+        if ::RUNTIME_ACTIVE.swap(true, Ordering::SeqCst) {
+            return Err(::runtime_unavailable());
+        }
+
+        let mut handle : *mut libc::c_void = ptr::null_mut();
+        let result = (functions.initialize)(argv_ptrs.len() as libc::c_int,
+            argv_ptrs.as_ptr(),
+            &parameters,
+            &mut handle);
+
+        // hostfxr's status codes are HRESULT-shaped: anything negative is a
+        // failure, and a failed call may still hand back a handle to close.
+        if result < 0 {
+            if !handle.is_null() {
+                (functions.close)(handle);
+            }
+
+            ::RUNTIME_ACTIVE.store(false, Ordering::SeqCst);
+            return Err(ClrError::Initialize(result));
+        }
+
+        let host = HostFxrHost {
+            hostfxr,
+            functions,
+            handle
+        };
+
+        for (key, value) in &self.properties {
+            let name = host_string(OsStr::new(key))?;
+            let value = host_string(OsStr::new(value))?;
+            let result = (host.functions.set_property)(host.handle, name.as_ptr(), value.as_ptr());
+            if result < 0 {
+                let err = error::hresult_to_error(result);
+                return Err(Error::new(err.kind(),
+                    format!("could not set runtime property {}: {}", key, err)).into());
+            }
+        }
+
+        Ok(host)
+    }
+}
+
+#[cfg(unix)]
+struct HostString(::std::ffi::CString);
+
+#[cfg(unix)]
+impl HostString {
+    fn as_ptr(&self) -> *const CharT {
+        self.0.as_ptr()
+    }
+}
+
+#[cfg(unix)]
+fn host_string(s: &OsStr) -> Result<HostString, ClrError> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(HostString(::std::ffi::CString::new(s.as_bytes())?))
+}
+
+#[cfg(windows)]
+struct HostString(Vec<u16>);
+
+#[cfg(windows)]
+impl HostString {
+    fn as_ptr(&self) -> *const CharT {
+        self.0.as_ptr()
+    }
+}
+
+#[cfg(windows)]
+fn host_string(s: &OsStr) -> Result<HostString, ClrError> {
+    use std::os::windows::ffi::OsStrExt;
+    let wide : Vec<u16> = s.encode_wide().collect();
+    if wide.contains(&0) {
+        return Err(Error::new(ErrorKind::InvalidInput,
+            format!("{:?} contains a NUL character", s)).into());
+    }
+
+    Ok(HostString(wide.into_iter().chain(Some(0)).collect()))
+}
@@ -1,133 +1,914 @@
+// only the platform code needs it; the C types come from std::os::raw, so
+// the crate builds on targets libc doesn't cover.
+#[cfg(any(unix, windows))]
 extern crate libc;
 
 mod loader;
+mod discovery;
+mod capture;
+mod error;
+mod logging;
+mod helper;
+mod runtime_config;
+// hostfxr has nothing to offer on a target coreclr can't be loaded on.
+#[cfg(all(feature = "hostfxr", any(unix, windows)))]
+mod hostfxr;
+#[cfg(unix)]
+mod remote;
+
+pub use error::ClrError;
+#[cfg(all(feature = "hostfxr", any(unix, windows)))]
+pub use hostfxr::{HostFxrBuilder, HostFxrHost};
+#[cfg(unix)]
+pub use remote::{RemoteClrHost, serve_remote_host};
 
 use std::default::Default;
-use std::path::{Path, PathBuf};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR_STR};
 use std::io::{self, Error, ErrorKind};
-use std::ffi::CString;
-use std::collections::HashSet;
-use std::panic;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
 use std::mem;
 use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// The separator CoreCLR expects between entries of path list properties
+/// such as TRUSTED_PLATFORM_ASSEMBLIES, matching the platform's PATH.
+pub const PATH_LIST_SEP: char = if cfg!(windows) { ';' } else { ':' };
+
+const DEFAULT_TPA_EXTENSIONS: [&str; 2] = ["dll", "exe"];
+
+// coreclr only supports one runtime per process, and can't start another
+// after shutting one down. this is set just before coreclr_initialize and
+// only cleared if that fails; RUNTIME_SHUT_DOWN tells the two refusals apart.
+static RUNTIME_ACTIVE: AtomicBool = AtomicBool::new(false);
+static RUNTIME_SHUT_DOWN: AtomicBool = AtomicBool::new(false);
+
+type InitializeFn = extern "C" fn( /* coreclr_initialize */
+    *const c_char,                     /* exePath */
+    *const c_char,                     /* appDomainFriendlyName */
+    c_int,                             /* propertyCount */
+    *mut *const c_char,                /* propertyKeys */
+    *mut *const c_char,                /* propertyValues */
+    *mut *mut c_void,                  /* hostHandle */
+    *mut c_uint                        /* domainId */
+) -> c_int;
+
+type ShutdownFn = extern "C" fn( /* coreclr_shutdown */
+    *mut c_void,                     /* hostHandle */
+    c_uint                           /* domainId */
+) -> c_int;
+
+type Shutdown2Fn = extern "C" fn( /* coreclr_shutdown_2 */
+    *mut c_void,                      /* hostHandle */
+    c_uint,                           /* domainId */
+    *mut c_int                        /* latchedExitCode */
+) -> c_int;
+
+type CreateDelegateFn = extern "C" fn( /* coreclr_create_delegate */
+    *mut c_void,                       /* hostHandle */
+    c_uint,                            /* domainId */
+    *const c_char,                     /* entryPointAssemblyName */
+    *const c_char,                     /* entryPointTypeName */
+    *const c_char,                     /* entryPointMethodName */
+    *mut *mut c_void                   /* delegate */
+) -> c_int;
+
+type ExecuteAssemblyFn = extern "C" fn( /* coreclr_execute_assembly */
+    *mut c_void,                        /* hostHandle */
+    c_uint,                             /* domainId */
+    c_int,                              /* argc */
+    *mut *const c_char,                 /* argv */
+    *const c_char,                      /* managedAssemblyPath */
+    *mut c_uint                         /* exitCode */
+) -> c_int;
+
+type ErrorWriterFn = extern "C" fn( /* error writer callback */
+    *const c_char                       /* message */
+);
+
+type SetErrorWriterFn = extern "C" fn( /* coreclr_set_error_writer */
+    Option<ErrorWriterFn>               /* errorWriter */
+) -> c_int;
+
+// the exception code the runtime exits with when managed code throws an
+// exception nobody catches.
+const UNHANDLED_EXCEPTION_CODE: u32 = 0xE0434352;
+
+thread_local! {
+    // whatever the runtime reported through the error writer while this
+    // thread was executing managed code.
+    static ERROR_OUTPUT: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// called by the runtime, possibly while it is tearing down, so this must
+// never panic. the message is only valid for the duration of the call.
+extern "C" fn error_writer(message: *const c_char) {
+    if message.is_null() {
+        return;
+    }
+
+    let text = unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned();
+    let _ = ERROR_OUTPUT.try_with(|output| {
+        if let Ok(mut output) = output.try_borrow_mut() {
+            output.push_str(&text);
+            if !text.ends_with('\n') {
+                output.push('\n');
+            }
+        }
+    });
+}
 
 struct ClrFunctions {
-    initialize: extern "C" fn(    /* coreclr_initialize */
-        *const libc::c_char,      /* exePath */
-        *const libc::c_char,      /* appDomainFriendlyName */
-        libc::c_int,              /* propertyCount */
-        *mut *const libc::c_char, /* propertyKeys */
-        *mut *const libc::c_char, /* propertyValues */
-        *mut *mut libc::c_void,   /* hostHandle */
-        *mut libc::c_uint         /* domainId */
-    ) -> libc::c_int,
-
-    shutdown: extern "C" fn(      /* coreclr_shutdown */
-        *mut libc::c_void,        /* hostHandle */
-        libc::c_uint              /* domainId */
-    ) -> libc::c_int,
-
-    create_delegate: extern "C" fn( /* coreclr_create_delegate */
-        *mut libc::c_void,          /* hostHandle */
-        libc::c_int,                /* domainId */
-        *const libc::c_char,        /* entryPointAssemblyName */
-        *const libc::c_char,        /* entryPointTypeName */
-        *const libc::c_char,        /* entryPointMethodName */
-        *mut *mut libc::c_void      /* delegate */
-    ) -> libc::c_int,
-
-    execute_assembly: extern "C" fn( /* coreclr_execute_assembly */
-        *mut libc::c_void,           /* hostHandle */
-        libc::c_uint,                /* domainId */
-        libc::c_int,                 /* argc */
-        *mut *const libc::c_char,    /* argv */
-        *const libc::c_char,         /* managedAssemblyPath */
-        *mut libc::c_uint            /* exitCode */
-    ) -> libc::c_int
+    initialize: InitializeFn,
+    shutdown: ShutdownFn,
+    // only present in newer runtimes.
+    shutdown_2: Option<Shutdown2Fn>,
+    create_delegate: CreateDelegateFn,
+    execute_assembly: ExecuteAssemblyFn
 }
 
 pub struct ClrHost {
     // this field is kept around so it can be dropped
     // at the end of ClrHost's lifetime
-    #[allow(dead_code)] 
     coreclr: loader::DynamicLibrary,
+    // loaded before coreclr, and unloaded after it.
+    #[allow(dead_code)]
+    preloaded: Vec<loader::DynamicLibrary>,
     coreclr_funs: ClrFunctions,
     coreclr_handle: *mut u8,
-    domain_id: usize
+    // exactly as coreclr_initialize hands it out; c_uint is u32 on every
+    // platform coreclr runs on.
+    domain_id: u32,
+    shutdown_done: bool,
+    output_capture: Option<capture::OutputCapture>,
+    assembly_load_paths: Vec<PathBuf>,
+    // coreclr_execute_assembly may only be called once per runtime. this
+    // is atomic rather than a Cell so that ClrHost stays RefUnwindSafe.
+    executed: AtomicBool,
+    delegate_cache: HashMap<(String, String, String), *mut c_void>,
+    working_directory: Option<PathBuf>,
+    coreclr_library_path: PathBuf,
+    logger: Option<logging::Logger>
+}
+
+/// The result of `ClrHost::execute_assembly_captured`.
+pub struct ExecuteResult {
+    pub exit_code: usize,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// How a call to `ClrHost::execute_assembly_outcome` ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecuteOutcome {
+    /// The managed program ran and exited with this code, which may well
+    /// be nonzero.
+    Exited(i32),
+    /// `coreclr_execute_assembly` itself failed with this HRESULT, so the
+    /// program may not have run at all.
+    HostError(i32),
+}
+
+/// The GC settings in effect, as reported by `ClrHost::gc_config`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GcConfig {
+    pub server_gc: bool,
+    pub latency_mode: GcLatencyMode,
+}
+
+/// Mirrors `System.Runtime.GCLatencyMode`. With the default settings,
+/// `Interactive` means concurrent GC is enabled and `Batch` that it isn't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GcLatencyMode {
+    Batch,
+    Interactive,
+    LowLatency,
+    SustainedLowLatency,
+    NoGCRegion,
+    /// A mode this crate doesn't know about yet.
+    Other(i32),
+}
+
+impl GcLatencyMode {
+    fn from_raw(mode: i32) -> GcLatencyMode {
+        match mode {
+            0 => GcLatencyMode::Batch,
+            1 => GcLatencyMode::Interactive,
+            2 => GcLatencyMode::LowLatency,
+            3 => GcLatencyMode::SustainedLowLatency,
+            4 => GcLatencyMode::NoGCRegion,
+            other => GcLatencyMode::Other(other)
+        }
+    }
+}
+
+/// A delegate returned by `ClrHost::create_delegate_info`, remembering
+/// which managed method it was created for.
+pub struct Delegate {
+    pub ptr: *mut c_void,
+    pub assembly: String,
+    pub type_name: String,
+    pub method: String,
+}
+
+impl Delegate {
+    /// Casts the delegate to the function pointer type `F`.
+    ///
+    /// # Safety
+    /// See `ClrHost::create_delegate_fn`.
+    pub unsafe fn as_fn<F: Copy>(&self) -> F {
+        debug_assert!(mem::size_of::<F>() == mem::size_of::<*mut c_void>(),
+            "delegate type must be a function pointer");
+        mem::transmute_copy::<*mut c_void, F>(&self.ptr)
+    }
+}
+
+impl fmt::Debug for Delegate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Delegate({}, {}::{} at {:p})", self.assembly, self.type_name, self.method, self.ptr)
+    }
+}
+
+/// Once initialized, CoreCLR can be called into from any thread, so the
+/// host handle and function pointers held here remain valid when the
+/// ClrHost moves to another thread. ClrHost is deliberately not `Sync`:
+/// methods such as `create_delegate` take `&mut self` and still require
+/// exclusive access.
+unsafe impl Send for ClrHost {}
+
+// the handle is only ever printed, never looked through.
+impl fmt::Debug for ClrHost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClrHost")
+            .field("domain_id", &self.domain_id)
+            .field("coreclr_library_path", &self.coreclr_library_path)
+            .field("coreclr_handle", &format_args!("{:p}", self.coreclr_handle))
+            .field("shutdown_done", &self.shutdown_done)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Drop for ClrHost {
     fn drop(&mut self) {
-        (self.coreclr_funs.shutdown)(self.coreclr_handle as *mut libc::c_void, self.domain_id as libc::c_uint);
+        self.shutdown_runtime(None);
+        RUNTIME_SHUT_DOWN.store(true, Ordering::SeqCst);
     }
 }
 
 impl ClrHost {
+    /// The id of the AppDomain that coreclr_initialize created for this host.
+    pub fn domain_id(&self) -> u32 {
+        self.domain_id
+    }
+
+    /// Always true; a ClrHost only exists once the runtime has been
+    /// successfully initialized.
+    pub fn is_initialized(&self) -> bool {
+        true
+    }
+
+    /// The coreclr library file this host loaded.
+    pub fn coreclr_library_path(&self) -> &Path {
+        &self.coreclr_library_path
+    }
+
+    /// The version of the loaded runtime, if it can be told. This is best
+    /// effort: coreclr doesn't export its version, so it's read from the
+    /// `.version` file a runtime install keeps next to the library.
+    pub fn coreclr_version(&self) -> Option<String> {
+        let dir = self.coreclr_library_path.parent()?;
+        let contents = fs::read_to_string(dir.join(".version")).ok()?;
+        // the first line is the commit the runtime was built from, the
+        // second its version.
+        contents.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .nth(1)
+            .map(|line| line.to_string())
+    }
+
+    /// The host handle returned by `coreclr_initialize`, for calling
+    /// hosting functions this crate doesn't wrap.
+    ///
+    /// # Safety
+    /// The handle is owned by this ClrHost and must not be used after it
+    /// is dropped or shut down.
+    pub unsafe fn raw_handle(&self) -> *mut c_void {
+        self.coreclr_handle as *mut c_void
+    }
+
+    /// Looks up an arbitrary export of the loaded coreclr library.
+    ///
+    /// # Safety
+    /// The returned pointer must be cast to the export's real type before
+    /// use, and must not be used after this ClrHost is dropped.
+    pub unsafe fn resolve_host_symbol(&self, name: &str) -> Result<*mut c_void, ClrError> {
+        self.coreclr.resolve_symbol(name).map_err(|_| ClrError::SymbolNotFound(name.to_string()))
+    }
+
+    /// Resolves a managed static method to a native-callable function pointer.
+    /// Results are cached, so resolving the same method again is cheap.
+    ///
+    /// # Safety
+    /// The returned pointer is only valid while this host is alive, and the
+    /// caller is responsible for calling it with the signature that the
+    /// managed method actually has.
     pub unsafe fn create_delegate(&mut self, 
         assembly_name: &str, 
         entry_point_type_name: &str, 
-        entry_point_method: &str) -> io::Result<*mut u8> {
-        let mut delegate : *mut libc::c_void = std::ptr::null_mut();
-        let assembly = try!(CString::new(assembly_name));
-        let ty = try!(CString::new(entry_point_type_name));
-        let method = try!(CString::new(entry_point_method));
+        entry_point_method: &str) -> Result<*mut u8, ClrError> {
+        let key = (assembly_name.to_string(), entry_point_type_name.to_string(), entry_point_method.to_string());
+        if let Some(&delegate) = self.delegate_cache.get(&key) {
+            return Ok(delegate as *mut _);
+        }
+
+        let delegate = self.create_delegate_uncached(assembly_name, entry_point_type_name, entry_point_method)?;
+        self.delegate_cache.insert(key, delegate);
+        Ok(delegate as *mut _)
+    }
+
+    fn create_delegate_uncached(&self,
+        assembly_name: &str,
+        entry_point_type_name: &str,
+        entry_point_method: &str) -> Result<*mut c_void, ClrError> {
+        let mut delegate : *mut c_void = std::ptr::null_mut();
+        let assembly = c_string(assembly_name, || "the assembly name".to_string())?;
+        let ty = c_string(entry_point_type_name, || "the type name".to_string())?;
+        let method = c_string(entry_point_method, || "the method name".to_string())?;
         let result = (self.coreclr_funs.create_delegate)(self.coreclr_handle as *mut _, 
-            self.domain_id as libc::c_int, 
+            self.domain_id,
             assembly.as_ptr(), 
             ty.as_ptr(), 
             method.as_ptr(),
             &mut delegate as *mut _);
         
         if result != 0 {
-            Err(Error::from_raw_os_error(result))
+            Err(ClrError::CreateDelegate(result))
+        } else {
+            Ok(delegate)
+        }
+    }
+
+    /// Like `create_delegate`, but returns the pointer together with the
+    /// assembly, type and method it came from, which makes delegates much
+    /// easier to tell apart when debugging.
+    ///
+    /// # Safety
+    /// See `create_delegate`.
+    pub unsafe fn create_delegate_info(&mut self,
+        assembly_name: &str,
+        entry_point_type_name: &str,
+        entry_point_method: &str) -> Result<Delegate, ClrError> {
+        let ptr = self.create_delegate(assembly_name, entry_point_type_name, entry_point_method)?;
+        Ok(Delegate {
+            ptr: ptr as *mut c_void,
+            assembly: assembly_name.to_string(),
+            type_name: entry_point_type_name.to_string(),
+            method: entry_point_method.to_string()
+        })
+    }
+
+    /// Like `create_delegate`, but finds the method by its metadata tokens
+    /// rather than by name, for assemblies whose names are obfuscated. The
+    /// method must be static and `[UnmanagedCallersOnly]`, and belong to
+    /// the type with `type_token`.
+    ///
+    /// This goes through the managed helper; see `helper_delegate`.
+    ///
+    /// # Safety
+    /// See `create_delegate`.
+    pub unsafe fn create_delegate_by_token(&mut self,
+        assembly_name: &str,
+        type_token: u32,
+        method_token: u32) -> Result<*mut u8, ClrError> {
+        let resolve_token = self.create_delegate_fn::<extern "C" fn(*const c_char, i32, i32) -> *mut c_void>(
+            helper::ASSEMBLY, helper::DIAGNOSTICS_TYPE, "ResolveMethodToken")
+            .map_err(|err| helper::unavailable(&err))?;
+        let assembly = c_string(assembly_name, || "the assembly name".to_string())?;
+        let delegate = resolve_token(assembly.as_ptr(), type_token as i32, method_token as i32);
+        if delegate.is_null() {
+            return Err(Error::new(ErrorKind::NotFound,
+                format!("no static [UnmanagedCallersOnly] method 0x{:08X} on type 0x{:08X} in assembly {}",
+                    method_token, type_token, assembly_name)).into());
+        }
+
+        Ok(delegate as *mut u8)
+    }
+
+    /// Resolves a method on a type in the managed helper assembly from
+    /// `helper/`. That's compiled into the crate and on the TPA with the
+    /// `embedded-helper` feature; otherwise it has to be on the assembly
+    /// probe path, and `Unsupported` is returned if it can't be found.
+    ///
+    /// # Safety
+    /// See `create_delegate`.
+    pub unsafe fn helper_delegate(&mut self, ty: &str, method: &str) -> Result<*mut u8, ClrError> {
+        self.create_delegate(helper::ASSEMBLY, ty, method)
+            .map_err(|err| helper::unavailable(&err).into())
+    }
+
+    /// Forgets every delegate resolved so far, so the next `create_delegate`
+    /// for each of them goes back to the runtime.
+    pub fn clear_delegate_cache(&mut self) {
+        self.delegate_cache.clear();
+    }
+
+    /// Like `create_delegate`, but identifies the assembly by its path, so
+    /// it works for assemblies only found once the runtime is running. One
+    /// in a directory passed to `ClrHostBuilder::with_assembly_probe_path`
+    /// is resolved by name as usual. Anything else is first loaded into
+    /// the default load context by path, which goes through the managed
+    /// helper; see `helper_delegate`. A method that can't be resolved is
+    /// reported as `NotFound`, naming the path it was looked for in.
+    ///
+    /// # Safety
+    /// See `create_delegate`.
+    pub unsafe fn create_delegate_from_path(&mut self,
+        assembly_path: &Path,
+        entry_point_type_name: &str,
+        entry_point_method: &str) -> Result<*mut u8, ClrError> {
+        if !assembly_path.is_file() {
+            return Err(Error::new(ErrorKind::NotFound,
+                format!("assembly {} does not exist", assembly_path.display())).into());
+        }
+
+        let dir = canonicalize_or_raw(assembly_path.parent().unwrap_or(Path::new(".")));
+        let assembly_name = if self.assembly_load_paths.iter().any(|p| canonicalize_or_raw(p) == dir) {
+            match assembly_path.file_stem().and_then(|s| s.to_str()) {
+                Some(name) => name.to_string(),
+                None => return Err(ClrError::NonUtf8Path(assembly_path.to_path_buf()))
+            }
+        } else {
+            self.load_assembly_from_path(assembly_path)?
+        };
+
+        self.create_delegate(&assembly_name, entry_point_type_name, entry_point_method)
+            .map_err(|err| Error::new(ErrorKind::NotFound,
+                format!("could not resolve {}::{} in assembly {} ({}): {}",
+                    entry_point_type_name, entry_point_method, assembly_name, assembly_path.display(), err)).into())
+    }
+
+    // loads an assembly the runtime couldn't find by name, returning the
+    // name it can be found by from then on.
+    fn load_assembly_from_path(&mut self, assembly_path: &Path) -> Result<String, ClrError> {
+        let path = absolute_assembly_path(assembly_path)?;
+        let path = match path.to_str() {
+            Some(path) => path,
+            None => return Err(ClrError::NonUtf8Path(path.clone()))
+        };
+
+        // the helper is ours, so its signature is known to match.
+        let mut load = unsafe {
+            self.create_string_delegate(helper::ASSEMBLY, helper::DIAGNOSTICS_TYPE, "LoadAssemblyFromPath", helper::FREE_METHOD)
+        }.map_err(|err| helper::unavailable(&err))?;
+
+        let output = load(path)?;
+        if let Some(message) = output.strip_prefix(helper::ERROR_PREFIX) {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("could not load assembly {}: {}", assembly_path.display(), message)).into());
+        }
+
+        Ok(output)
+    }
+
+    /// Like `create_delegate`, but hands back the delegate already cast to
+    /// the function pointer type `F`, e.g.
+    /// `host.create_delegate_fn::<extern "C" fn(i32) -> i32>(...)`.
+    ///
+    /// Structs are best exchanged by pointer. Declare them `#[repr(C)]` on
+    /// the Rust side and `[StructLayout(LayoutKind.Sequential)]` on the
+    /// managed side, with fields of matching size in the same order, and
+    /// stick to blittable field types (integers, floats, pointers; no
+    /// `bool`, `String` or references):
+    ///
+    /// ```text
+    /// #[repr(C)] struct InArgs { iterations: i32, scale: f64 }
+    /// #[repr(C)] struct OutResult { total: f64, count: i32 }
+    ///
+    /// let run = host.create_delegate_fn::<
+    ///     extern "C" fn(*const InArgs, *mut OutResult) -> i32>("Plugin", "Plugin.Entry", "Run")?;
+    /// let mut out = OutResult { total: 0.0, count: 0 };
+    /// let status = run(&InArgs { iterations: 10, scale: 0.5 }, &mut out);
+    ///
+    /// // managed side
+    /// [UnmanagedCallersOnly]
+    /// public static unsafe int Run(InArgs* input, OutResult* output)
+    /// {
+    ///     output->Total = input->Iterations * input->Scale;
+    ///     output->Count = input->Iterations;
+    ///     return 0;
+    /// }
+    /// ```
+    ///
+    /// Both pointers only need to be valid for the duration of the call;
+    /// managed code must not hold on to them.
+    ///
+    /// Managed code can call back into Rust through an `extern "C"`
+    /// function pointer passed as an argument, which it receives as a
+    /// `delegate* unmanaged<int, void>`:
+    ///
+    /// ```no_run
+    /// # use coreclr::{ClrError, ClrHostBuilder};
+    /// extern "C" fn progress(percent: i32) {
+    ///     // a panic must not escape here; see below.
+    ///     let _ = std::panic::catch_unwind(|| println!("{}% done", percent));
+    /// }
+    ///
+    /// # fn main() -> Result<(), ClrError> {
+    /// # let mut host = ClrHostBuilder::new().build()?;
+    /// let process = unsafe {
+    ///     host.create_delegate_fn::<extern "C" fn(extern "C" fn(i32))>("Plugin", "Plugin.Entry", "Process")?
+    /// };
+    /// process(progress);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The callback runs on whichever thread managed code calls it from,
+    /// with managed frames below it on the stack. Unwinding a Rust panic
+    /// through those frames is undefined behavior, and `execute_assembly`'s
+    /// own `catch_unwind` can't help since the panic would have to cross
+    /// managed code to get there. Callbacks must therefore catch their own
+    /// panics, as above, or be built with `panic = "abort"`.
+    ///
+    /// # Safety
+    /// `F` must be an `extern "C"` function pointer type whose signature
+    /// matches the managed method. The same lifetime caveats as
+    /// `create_delegate` apply.
+    pub unsafe fn create_delegate_fn<F: Copy>(&mut self,
+        assembly_name: &str,
+        entry_point_type_name: &str,
+        entry_point_method: &str) -> Result<F, ClrError> {
+        debug_assert!(mem::size_of::<F>() == mem::size_of::<*mut c_void>(),
+            "delegate type must be a function pointer");
+        let delegate = self.create_delegate(assembly_name, entry_point_type_name, entry_point_method)?;
+        Ok(mem::transmute_copy::<*mut u8, F>(&delegate))
+    }
+
+    /// Resolves and calls a managed method that takes no arguments and
+    /// returns nothing, which must be declared as:
+    ///
+    /// ```text
+    /// [UnmanagedCallersOnly]
+    /// public static void Method()
+    /// ```
+    ///
+    /// The method is looked up through the managed helper, which checks
+    /// its signature first, so anything else is refused as `NotFound`
+    /// rather than called; see `helper_delegate`. Nothing can unwind back
+    /// out of the call: managed exceptions don't cross it, and Rust
+    /// callbacks the method calls must catch their own panics.
+    pub fn invoke_void(&mut self,
+        assembly_name: &str,
+        entry_point_type_name: &str,
+        entry_point_method: &str) -> Result<(), ClrError> {
+        // the helper is ours, so its signature is known to match.
+        let resolve_void = unsafe {
+            self.create_delegate_fn::<extern "C" fn(*const c_char, *const c_char, *const c_char) -> *mut c_void>(
+                helper::ASSEMBLY, helper::DIAGNOSTICS_TYPE, "ResolveVoidMethod")
+        }.map_err(|err| helper::unavailable(&err))?;
+        let assembly = c_string(assembly_name, || "the assembly name".to_string())?;
+        let type_name = c_string(entry_point_type_name, || "the type name".to_string())?;
+        let method = c_string(entry_point_method, || "the method name".to_string())?;
+        let delegate = resolve_void(assembly.as_ptr(), type_name.as_ptr(), method.as_ptr());
+        if delegate.is_null() {
+            return Err(Error::new(ErrorKind::NotFound,
+                format!("no static [UnmanagedCallersOnly] void {}::{}() in assembly {}",
+                    entry_point_type_name, entry_point_method, assembly_name)).into());
+        }
+
+        // the helper only hands back methods with exactly this signature.
+        let delegate = unsafe { mem::transmute::<*mut c_void, extern "C" fn()>(delegate) };
+        delegate();
+        Ok(())
+    }
+
+    /// Resolves and calls a managed method shaped like a C `main`, returning
+    /// its result. Unlike `execute_assembly`, this can be called any number
+    /// of times. The managed side should look like:
+    ///
+    /// ```text
+    /// [UnmanagedCallersOnly]
+    /// public static int Method(int argc, IntPtr argv)
+    /// ```
+    ///
+    /// where `argv` points at `argc` UTF-8 strings.
+    ///
+    /// # Safety
+    /// The managed method must have the signature above.
+    pub unsafe fn invoke_int_delegate(&mut self,
+        assembly_name: &str,
+        entry_point_type_name: &str,
+        entry_point_method: &str,
+        args: &[&str]) -> Result<i32, ClrError> {
+        let delegate = self.create_delegate_fn::<extern "C" fn(c_int, *mut *const c_char) -> c_int>(
+            assembly_name, entry_point_type_name, entry_point_method)?;
+        let mut argv = Argv::new(args)?;
+        Ok(delegate(argv.argc(), argv.as_mut_ptr()))
+    }
+
+    /// Wraps a managed method that takes and returns a UTF-8 string in a
+    /// closure that does the marshaling. The managed side is expected to
+    /// look like this, with both methods on the same type:
+    ///
+    /// ```text
+    /// [UnmanagedCallersOnly]
+    /// public static IntPtr Method(IntPtr input)
+    /// {
+    ///     string result = ... Marshal.PtrToStringUTF8(input) ...;
+    ///     return Marshal.StringToCoTaskMemUTF8(result);
+    /// }
+    ///
+    /// [UnmanagedCallersOnly]
+    /// public static void Free(IntPtr str) => Marshal.FreeCoTaskMem(str);
+    /// ```
+    ///
+    /// The string returned by `entry_point_method` is copied and then
+    /// handed back to `free_method`.
+    ///
+    /// # Safety
+    /// The managed methods must have the signatures above, and the
+    /// returned closure must not be called after this host is dropped.
+    pub unsafe fn create_string_delegate(&mut self,
+        assembly_name: &str,
+        entry_point_type_name: &str,
+        entry_point_method: &str,
+        free_method: &str) -> Result<impl FnMut(&str) -> Result<String, ClrError>, ClrError> {
+        let invoke = self.create_delegate_fn::<extern "C" fn(*const c_char) -> *mut c_char>(
+            assembly_name, entry_point_type_name, entry_point_method)?;
+        let free = self.create_delegate_fn::<extern "C" fn(*mut c_char)>(
+            assembly_name, entry_point_type_name, free_method)?;
+        Ok(move |input: &str| {
+            let input = c_string(input, || "the input string".to_string())?;
+            let result = invoke(input.as_ptr());
+            if result.is_null() {
+                return Err(Error::new(ErrorKind::InvalidData, "managed string delegate returned null").into());
+            }
+
+            let copied = CStr::from_ptr(result).to_str().map(|s| s.to_string());
+            free(result);
+            copied.map_err(|_| Error::new(ErrorKind::InvalidData, "managed string delegate returned non-UTF-8 data").into())
+        })
+    }
+
+    /// Lists the `[UnmanagedCallersOnly]` methods of `assembly` as
+    /// `Namespace.Type::Method`, to help track down why a
+    /// `create_delegate` call can't find a method.
+    ///
+    /// This runs in the managed helper assembly built from the crate's
+    /// `helper/` directory, which must be on the assembly probe path unless
+    /// the `embedded-helper` feature builds it in. Otherwise an
+    /// `Unsupported` error is returned.
+    pub fn list_unmanaged_callers(&mut self, assembly: &str) -> Result<Vec<String>, ClrError> {
+        // the helper is ours, so its signature is known to match.
+        let mut list = unsafe {
+            self.create_string_delegate(helper::ASSEMBLY, helper::DIAGNOSTICS_TYPE, "ListUnmanagedCallers", helper::FREE_METHOD)
+        }.map_err(|err| helper::unavailable(&err))?;
+
+        let output = list(assembly)?;
+        if let Some(message) = output.strip_prefix(helper::ERROR_PREFIX) {
+            return Err(Error::new(ErrorKind::NotFound,
+                format!("could not inspect assembly {}: {}", assembly, message)).into());
+        }
+
+        Ok(output.lines().map(|line| line.to_string()).collect())
+    }
+
+    /// Reports the GC configuration the runtime actually ended up with,
+    /// which isn't always what was asked for (server GC is ignored on a
+    /// single-core machine, for instance).
+    ///
+    /// Like `list_unmanaged_callers`, this needs the managed helper from
+    /// `helper/` on the assembly probe path, and returns `Unsupported`
+    /// without it.
+    pub fn gc_config(&self) -> Result<GcConfig, ClrError> {
+        let get_gc_config = self.create_delegate_uncached(helper::ASSEMBLY, helper::DIAGNOSTICS_TYPE, "GetGcConfig")
+            .map_err(|err| helper::unavailable(&err))?;
+        // the helper is ours, so its signature is known to match.
+        let get_gc_config = unsafe {
+            mem::transmute::<*mut c_void, extern "C" fn(*mut c_int, *mut c_int)>(get_gc_config)
+        };
+
+        let mut is_server : c_int = 0;
+        let mut latency_mode : c_int = 0;
+        get_gc_config(&mut is_server, &mut latency_mode);
+        Ok(GcConfig {
+            server_gc: is_server != 0,
+            latency_mode: GcLatencyMode::from_raw(latency_mode)
+        })
+    }
+
+    /// Shuts down the runtime, reporting a failure instead of ignoring it
+    /// the way dropping the host does. The host won't shut down again when
+    /// it's dropped afterwards.
+    pub fn shutdown(mut self) -> Result<(), ClrError> {
+        let result = self.shutdown_runtime(None);
+        if result != 0 {
+            Err(ClrError::Shutdown(result))
         } else {
-            Ok(delegate as *mut _)
+            Ok(())
         }
     }
 
-    pub fn execute_assembly<T: Into<PathBuf>>(&self, args: &[&str], assembly_path: T) -> io::Result<usize> {
+    /// Shuts down the runtime and returns the exit code latched by managed
+    /// code (e.g. through `Environment.Exit`). Runtimes that don't export
+    /// `coreclr_shutdown_2` have no latched exit code, so this returns 0
+    /// for them.
+    pub fn shutdown_with_exit_code(mut self) -> Result<i32, ClrError> {
+        let mut exit_code : c_int = 0;
+        let result = self.shutdown_runtime(Some(&mut exit_code));
+        if result != 0 {
+            Err(ClrError::Shutdown(result))
+        } else {
+            Ok(exit_code as i32)
+        }
+    }
+
+    // the only place the runtime gets shut down from, so that however the
+    // host goes away it only happens once. returns the HRESULT, which is
+    // 0 if there was nothing left to do.
+    fn shutdown_runtime(&mut self, latched_exit_code: Option<&mut c_int>) -> c_int {
+        if self.shutdown_done {
+            return 0;
+        }
+
+        self.shutdown_done = true;
+        logging::log(self.logger.as_ref(), || "shutting down the runtime".to_string());
+        let handle = self.coreclr_handle as *mut c_void;
+        match (latched_exit_code, self.coreclr_funs.shutdown_2) {
+            (Some(exit_code), Some(shutdown_2)) => shutdown_2(handle, self.domain_id, exit_code as *mut _),
+            _ => (self.coreclr_funs.shutdown)(handle, self.domain_id)
+        }
+    }
+
+    /// Runs the assembly's entry point. `Ok` holds the managed program's
+    /// exit code, whatever it is; `Err` means the host call itself failed,
+    /// and carries the decoded HRESULT.
+    ///
+    /// The runtime only supports executing an assembly once, so any later
+    /// call returns an error. Use `create_delegate` for managed code that
+    /// needs to be invoked repeatedly.
+    ///
+    /// `args` are handed to the runtime as UTF-8, which is what it expects
+    /// on every platform, so non-ASCII arguments such as accented file
+    /// names reach `Main` as they were given.
+    pub fn execute_assembly<T: Into<PathBuf>>(&self, args: &[&str], assembly_path: T) -> Result<usize, ClrError> {
         let buf = assembly_path.into();
-        let result = panic::catch_unwind(|| {
+        // nothing a panic could interrupt leaves the host half-updated:
+        // `executed` is a single atomic store, the working directory is put
+        // back by CwdGuard as the panic unwinds, and ERROR_OUTPUT is cleared
+        // before every run. so the host is fine to use afterwards, whatever
+        // fields it grows that the compiler can't prove that for.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
             self.execute_assembly_impl(args, &buf)
-        });
+        }));
 
         match result {
             Ok(Ok(return_code)) => Ok(return_code),
             Ok(Err(err)) => Err(err),
-            Err(_) => Err(Error::new(ErrorKind::Other, "unhandled CLR exception"))
+            // managed exceptions don't unwind through the FFI boundary,
+            // so the only thing that can land here is a Rust panic.
+            Err(_) => Err(Error::other("panicked while executing the assembly").into())
         }
     }
 
-    fn execute_assembly_impl(&self, args: &[&str], assembly_path: &Path) -> io::Result<usize> {
-        let mut argv = vec![];
-        for arg in args {
-            argv.push(try!(CString::new(*arg)));
+    /// Like `execute_assembly`, but reports a failed host call as
+    /// `ExecuteOutcome::HostError` instead of folding it into `Err`, so it
+    /// can't be mistaken for the program exiting with a nonzero code.
+    /// `Err` is left for problems on this side of the call, and for crashes
+    /// that came with a report (`ClrError::UnhandledException`).
+    pub fn execute_assembly_outcome<T: Into<PathBuf>>(&self, args: &[&str], assembly_path: T) -> Result<ExecuteOutcome, ClrError> {
+        match self.execute_assembly(args, assembly_path) {
+            Ok(exit_code) => Ok(ExecuteOutcome::Exited(exit_code as i32)),
+            Err(ClrError::Execute(hr)) => Ok(ExecuteOutcome::HostError(hr)),
+            Err(err) => Err(err)
         }
+    }
 
-        let asm_path = if let Some(p) = assembly_path.to_str() {
-            try!(CString::new(p))
-        } else {
-            return Err(Error::new(ErrorKind::InvalidInput, "assembly path is not UTF-8"));
+    /// Same as `execute_assembly`, but takes ownership of the arguments so
+    /// that e.g. `env::args().collect()` can be passed straight through.
+    pub fn execute_assembly_owned<T: Into<PathBuf>>(&self, args: Vec<String>, assembly_path: T) -> Result<usize, ClrError> {
+        let borrowed : Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        self.execute_assembly(&borrowed, assembly_path)
+    }
+
+    /// Runs `execute_assembly` on a new thread and returns immediately.
+    /// Since an assembly can only be executed once per host, the host moves
+    /// onto the thread and is shut down there once the assembly exits.
+    pub fn execute_assembly_spawn<T: Into<PathBuf>>(self, args: Vec<String>, assembly_path: T) -> JoinHandle<Result<usize, ClrError>> {
+        let path = assembly_path.into();
+        thread::spawn(move || {
+            self.execute_assembly_owned(args, path)
+        })
+    }
+
+    /// Like `execute_assembly_spawn`, but waits at most `timeout` for the
+    /// assembly to exit, returning a `TimedOut` error if it doesn't.
+    ///
+    /// Managed code can't be safely interrupted, so after a timeout the
+    /// assembly keeps running on its thread and still owns the runtime.
+    /// The process should be considered poisoned at that point; the only
+    /// reliable way out is to exit it.
+    pub fn execute_assembly_timeout<T: Into<PathBuf>>(self,
+        args: Vec<String>,
+        assembly_path: T,
+        timeout: Duration) -> Result<usize, ClrError> {
+        let path = assembly_path.into();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(self.execute_assembly_owned(args, path));
+        });
+
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(Error::new(ErrorKind::TimedOut,
+                format!("the assembly did not exit within {:?}", timeout)).into()),
+            Err(mpsc::RecvTimeoutError::Disconnected) =>
+                Err(Error::other("the thread executing the assembly died").into())
+        }
+    }
+
+    /// Executes the assembly and returns its exit code together with
+    /// everything written to stdout and stderr while it ran. Output is
+    /// only captured if the host was built with
+    /// `ClrHostBuilder::with_captured_output`; otherwise both are empty.
+    pub fn execute_assembly_captured<T: Into<PathBuf>>(&self, args: &[&str], assembly_path: T) -> Result<ExecuteResult, ClrError> {
+        let exit_code = self.execute_assembly(args, assembly_path)?;
+        let (stdout, stderr) = match self.output_capture {
+            Some(ref capture) => capture.take(),
+            None => (vec![], vec![])
+        };
+
+        Ok(ExecuteResult {
+            exit_code,
+            stdout,
+            stderr
+        })
+    }
+
+    fn execute_assembly_impl(&self, args: &[&str], assembly_path: &Path) -> Result<usize, ClrError> {
+        // coreclr_execute_assembly wants UTF-8 even on Windows, which is
+        // what a &str already is.
+        let mut argv = Argv::new(args)?;
+        let asm_path = c_string(path_bytes(&absolute_assembly_path(assembly_path)?)?,
+            || format!("assembly path {}", assembly_path.display()))?;
+
+        let cwd_guard = match self.working_directory {
+            Some(ref dir) => Some(CwdGuard::set(dir)?),
+            None => None
         };
 
-        let mut pointer_vec : Vec<_> = argv.iter().map(|x| x.as_ptr()).collect();
+        // only set once nothing on this side can fail any more, so a bad
+        // argument or path doesn't use up the one execution there is.
+        if self.executed.swap(true, Ordering::SeqCst) {
+            return Err(Error::other("assembly already executed").into());
+        }
 
-        let mut return_code : libc::c_uint = 0;
+        ERROR_OUTPUT.with(|output| output.borrow_mut().clear());
+        let mut return_code : c_uint = 0;
         let result = (self.coreclr_funs.execute_assembly)(self.coreclr_handle as *mut _,
-                self.domain_id as libc::c_uint,
-                pointer_vec.len() as libc::c_int,
-                pointer_vec.as_mut_ptr(),
+                self.domain_id,
+                argv.argc(),
+                argv.as_mut_ptr(),
                 asm_path.as_ptr(),
                 &mut return_code as *mut _);
 
-        if result != 0 {
-            return Err(Error::from_raw_os_error(result));
+        drop(cwd_guard);
+        let crashed = result != 0 || return_code == UNHANDLED_EXCEPTION_CODE;
+        let error_output = ERROR_OUTPUT.with(|output| mem::take(&mut *output.borrow_mut()));
+        if crashed && !error_output.is_empty() {
+            let code = if result != 0 { result } else { return_code as i32 };
+            Err(ClrError::UnhandledException(code, error_output))
+        } else if result != 0 {
+            Err(ClrError::Execute(result))
         } else {
-            return Ok(return_code as usize)
-        };
+            Ok(return_code as usize)
+        }
     }
 }
 
+/// Configures and starts a `ClrHost`. The `with_*` methods take and return
+/// `&mut self`, so a builder can be set up inline and built in one go:
+///
+/// ```no_run
+/// # use coreclr::ClrHostBuilder;
+/// let host = ClrHostBuilder::new()
+///     .with_server_gc()
+///     .with_coreclr_path("/usr/share/dotnet/shared/Microsoft.NETCore.App/8.0.0")
+///     .build();
+/// ```
+///
+/// To hand an inline-configured builder to something that wants it by
+/// value, finish the chain with `take`.
+#[derive(Clone, Debug)]
 pub struct ClrHostBuilder {
     server_gc: bool,
     concurrent_gc: bool,
@@ -136,6 +917,44 @@ pub struct ClrHostBuilder {
     appdomain_name: Option<String>,
     assembly_load_paths: Vec<PathBuf>,
     native_library_search_paths: Vec<PathBuf>,
+    properties: Vec<(String, String)>,
+    recursive_tpa: bool,
+    runtime_search_error: Option<String>,
+    gc_heap_hard_limit: Option<u64>,
+    gc_heap_hard_limit_percent: Option<u8>,
+    captured_output: bool,
+    tiered_compilation: Option<bool>,
+    tiered_pgo: Option<bool>,
+    ready_to_run: Option<bool>,
+    global_symbols: bool,
+    load_mode: loader::LoadMode,
+    startup_hooks: Vec<PathBuf>,
+    self_contained_app: Option<PathBuf>,
+    working_directory: Option<PathBuf>,
+    coreclr_library_file: Option<PathBuf>,
+    trusted_platform_assemblies: Vec<PathBuf>,
+    appdomain_compat_switch: Option<String>,
+    tpa_extensions: Vec<String>,
+    native_image_probe_paths: Vec<PathBuf>,
+    gc_heap_count: Option<u32>,
+    gc_no_affinitize: Option<bool>,
+    preload_libraries: Vec<PathBuf>,
+    tpa_priority: Vec<String>,
+    invariant_globalization: bool,
+    app_context_base_directory: Option<PathBuf>,
+    coreclr_autodiscover: bool,
+    coreclr_filename: Option<String>,
+    quick_jit: Option<bool>,
+    quick_jit_for_loops: Option<bool>,
+    on_stack_replacement: Option<bool>,
+    assembly_directory_on_probe_path: bool,
+    runtime_config: Option<PathBuf>,
+    runtime_config_properties: Vec<(String, String)>,
+    native_search_directories_raw: Option<String>,
+    threadpool_min_threads: Option<u32>,
+    threadpool_max_threads: Option<u32>,
+    diagnostic_ports: Vec<String>,
+    logger: Option<logging::Logger>,
 }
 
 impl Default for ClrHostBuilder {
@@ -148,15 +967,96 @@ impl Default for ClrHostBuilder {
             appdomain_name: None,
             assembly_load_paths: vec![],
             native_library_search_paths: vec![],
+            properties: vec![],
+            recursive_tpa: false,
+            runtime_search_error: None,
+            gc_heap_hard_limit: None,
+            gc_heap_hard_limit_percent: None,
+            captured_output: false,
+            tiered_compilation: None,
+            tiered_pgo: None,
+            ready_to_run: None,
+            global_symbols: false,
+            load_mode: loader::LoadMode::default(),
+            startup_hooks: vec![],
+            self_contained_app: None,
+            working_directory: None,
+            coreclr_library_file: None,
+            trusted_platform_assemblies: vec![],
+            appdomain_compat_switch: None,
+            tpa_extensions: DEFAULT_TPA_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            native_image_probe_paths: vec![],
+            gc_heap_count: None,
+            gc_no_affinitize: None,
+            preload_libraries: vec![],
+            tpa_priority: vec![],
+            invariant_globalization: false,
+            app_context_base_directory: None,
+            coreclr_autodiscover: false,
+            coreclr_filename: None,
+            quick_jit: None,
+            quick_jit_for_loops: None,
+            on_stack_replacement: None,
+            assembly_directory_on_probe_path: false,
+            runtime_config: None,
+            runtime_config_properties: vec![],
+            native_search_directories_raw: None,
+            threadpool_min_threads: None,
+            threadpool_max_threads: None,
+            diagnostic_ports: vec![],
+            logger: None,
         }
     }
 }
 
+// what `ClrHostBuilder::prepare` hands to coreclr_initialize.
+struct PreparedHost {
+    coreclr_path: PathBuf,
+    assembly: CString,
+    name: CString,
+    properties: Vec<(CString, CString)>,
+    assembly_load_paths: Vec<PathBuf>,
+}
+
 impl ClrHostBuilder {
     pub fn new() -> ClrHostBuilder {
         Default::default()
     }
 
+    /// Moves the configuration out into a new builder, leaving this one
+    /// with the defaults. This is how a chain of `with_*` calls on a
+    /// temporary ends up as an owned builder, e.g.
+    /// `run(ClrHostBuilder::new().with_server_gc().take())`.
+    pub fn take(&mut self) -> ClrHostBuilder {
+        mem::take(self)
+    }
+
+    pub fn server_gc(&self) -> bool {
+        self.server_gc
+    }
+
+    pub fn concurrent_gc(&self) -> bool {
+        self.concurrent_gc
+    }
+
+    /// The coreclr directory, as set by `with_coreclr_path` or one of the
+    /// discovery methods.
+    pub fn coreclr_path(&self) -> Option<&Path> {
+        self.coreclr_path.as_deref()
+    }
+
+    pub fn assembly(&self) -> Option<&Path> {
+        self.assembly.as_deref()
+    }
+
+    pub fn assembly_probe_paths(&self) -> &[PathBuf] {
+        &self.assembly_load_paths
+    }
+
+    pub fn native_library_probe_paths(&self) -> &[PathBuf] {
+        &self.native_library_search_paths
+    }
+
     pub fn with_server_gc(&mut self) -> &mut ClrHostBuilder {
         self.server_gc = true;
         self
@@ -167,6 +1067,12 @@ impl ClrHostBuilder {
         self
     }
 
+    /// Picks server GC if `enabled`, workstation GC otherwise.
+    pub fn with_server_gc_enabled(&mut self, enabled: bool) -> &mut ClrHostBuilder {
+        self.server_gc = enabled;
+        self
+    }
+
     pub fn with_concurrent_gc(&mut self) -> &mut ClrHostBuilder {
         self.concurrent_gc = true;
         self
@@ -177,201 +1083,1300 @@ impl ClrHostBuilder {
         self
     }
 
-    pub fn with_coreclr_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
-        self.coreclr_path = Some(path.into());
+    /// Picks concurrent GC if `enabled`, non-concurrent GC otherwise.
+    pub fn with_concurrent_gc_enabled(&mut self, enabled: bool) -> &mut ClrHostBuilder {
+        self.concurrent_gc = enabled;
         self
     }
 
-    pub fn with_assembly_probe_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
-        self.assembly_load_paths.push(path.into());
+    /// Caps the size of the managed heap, in bytes.
+    pub fn with_gc_heap_hard_limit(&mut self, bytes: u64) -> &mut ClrHostBuilder {
+        self.gc_heap_hard_limit = Some(bytes);
         self
     }
 
-    pub fn with_native_library_probe_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
-        self.native_library_search_paths.push(path.into());
+    /// Caps the size of the managed heap as a percentage (1-100) of
+    /// physical memory. Out-of-range values are rejected by `build()`.
+    pub fn with_gc_heap_hard_limit_percent(&mut self, percent: u8) -> &mut ClrHostBuilder {
+        self.gc_heap_hard_limit_percent = Some(percent);
         self
     }
 
-    pub fn with_assembly<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
-        self.assembly = Some(path.into());
+    /// Sets how many heaps (and GC threads) the server GC uses. Only valid
+    /// together with server GC; `build()` rejects it otherwise.
+    pub fn with_gc_heap_count(&mut self, n: u32) -> &mut ClrHostBuilder {
+        self.gc_heap_count = Some(n);
         self
     }
 
-    pub fn with_appdomain_name<T: Into<String>>(&mut self, name: T) -> &mut ClrHostBuilder {
-        self.appdomain_name = Some(name.into());
+    /// Sets whether the server GC's threads are left unpinned from their
+    /// cores. Only valid together with server GC; `build()` rejects it
+    /// otherwise.
+    pub fn with_gc_no_affinitize(&mut self, no_affinitize: bool) -> &mut ClrHostBuilder {
+        self.gc_no_affinitize = Some(no_affinitize);
         self
     }
 
-    pub fn build(&self) -> io::Result<ClrHost> {
-        let coreclr_path = if let Some(ref p) = self.coreclr_path {
-            if let Some(s) = p.to_str() {
-                s.to_string()
-            } else {
-                return Err(Error::new(ErrorKind::InvalidInput, "coreclr path is not valid UTF-8"));
-            }
-        } else {
-            return Err(Error::new(ErrorKind::NotFound, "no path to coreclr provided"));
-        };
+    /// Sets `System.Runtime.TieredCompilation`. Requires .NET Core 2.1 or
+    /// later; it's on by default starting with .NET Core 3.0.
+    pub fn with_tiered_compilation(&mut self, enabled: bool) -> &mut ClrHostBuilder {
+        self.tiered_compilation = Some(enabled);
+        self
+    }
 
-        let assembly_path = if let Some(ref p) = self.assembly {
-            if let Some(s) = p.to_str() {
-                s.to_string()
-            } else {
-                return Err(Error::new(ErrorKind::InvalidInput, "assembly path is not valid UTF-8"));
-            }
-        } else {
-            return Err(Error::new(ErrorKind::NotFound, "no assembly provided"));
-        }; 
+    /// Sets `System.Runtime.TieredPGO`. Requires .NET 6 or later; it's on
+    /// by default starting with .NET 8.
+    pub fn with_tiered_pgo(&mut self, enabled: bool) -> &mut ClrHostBuilder {
+        self.tiered_pgo = Some(enabled);
+        self
+    }
 
-        // every list expected by the runtime here is colon-delimited.
+    /// Sets `System.Runtime.TieredCompilation.QuickJit`, which lets tier 0
+    /// code be jitted without optimizations. Requires .NET Core 3.0 or
+    /// later, where it's on by default.
+    pub fn with_quick_jit(&mut self, enabled: bool) -> &mut ClrHostBuilder {
+        self.quick_jit = Some(enabled);
+        self
+    }
 
-        // first - building native search directory paths.
-        // by default, the directory where libcoreclr resides is
-        // probed by the runtime for PInvoke targets.
-        let mut native_search_path = String::new();
-        native_search_path.push_str(&coreclr_path);
-        for path in self.native_library_search_paths.iter() {
-            if let Some(s) = path.to_str() {
-                native_search_path.push(':');
-                native_search_path.push_str(s);
-            } else {
-                return Err(Error::new(ErrorKind::InvalidInput, "native search path is not valid UTF-8"));
-            }
-        }
+    /// Sets `System.Runtime.TieredCompilation.QuickJitForLoops`, which
+    /// extends quick JIT to methods containing loops. Requires .NET Core
+    /// 3.0 or later; it's on by default starting with .NET 7, where OSR
+    /// keeps such methods from getting stuck in slow code.
+    pub fn with_quick_jit_for_loops(&mut self, enabled: bool) -> &mut ClrHostBuilder {
+        self.quick_jit_for_loops = Some(enabled);
+        self
+    }
 
-        // second - load coreclr.
-        let actual_path = self.coreclr_path.clone().unwrap();
-        let mut coreclr_path = actual_path.clone();
-        if cfg!(target_os = "macos") {
-            coreclr_path.push("libcoreclr.dylib");
-        } else if cfg!(target_os = "linux") {
-            coreclr_path.push("libcoreclr.so");
-        } else {
-            coreclr_path.push("coreclr.dll");
-        }
+    /// Controls On-Stack Replacement, which moves long-running loops in
+    /// quick-jitted methods over to optimized code. Requires .NET 7 or
+    /// later (on x64 and arm64). Like `with_ready_to_run`, this can only
+    /// be set through the environment, as `DOTNET_TC_OnStackReplacement`.
+    pub fn with_osr(&mut self, enabled: bool) -> &mut ClrHostBuilder {
+        self.on_stack_replacement = Some(enabled);
+        self
+    }
 
-        let lib = try!(loader::DynamicLibrary::load(&coreclr_path));
-        // load our function pointers.
-        let functions = unsafe {
-            ClrFunctions {
-                initialize: mem::transmute(try!(lib.resolve_symbol("coreclr_initialize"))),
-                shutdown: mem::transmute(try!(lib.resolve_symbol("coreclr_shutdown"))),
-                create_delegate: mem::transmute(try!(lib.resolve_symbol("coreclr_create_delegate"))),
-                execute_assembly: mem::transmute(try!(lib.resolve_symbol("coreclr_execute_assembly")))
-            }
-        };
+    /// Sets `System.Threading.ThreadPool.MinThreads`, the number of worker
+    /// threads the thread pool creates on demand before it starts
+    /// throttling thread creation.
+    pub fn with_threadpool_min_threads(&mut self, threads: u32) -> &mut ClrHostBuilder {
+        self.threadpool_min_threads = Some(threads);
+        self
+    }
 
-        // build up CStrings to send to coreclr.
-        let mut probe_paths = String::new();
-        for path in &self.assembly_load_paths {
-            if let Some(s) = path.to_str() {
-                if probe_paths.len() != 0 {
-                    probe_paths.push(':');
-                }
+    /// Sets `System.Threading.ThreadPool.MaxThreads`. `build()` rejects a
+    /// maximum below the minimum.
+    pub fn with_threadpool_max_threads(&mut self, threads: u32) -> &mut ClrHostBuilder {
+        self.threadpool_max_threads = Some(threads);
+        self
+    }
 
-                probe_paths.push_str(s);
-            } else {
-                return Err(Error::new(ErrorKind::InvalidInput, "native search path is not valid UTF-8"));
-            }
-        }
+    /// Has the runtime open a diagnostic port at `address` (a socket path
+    /// on Unix, a named pipe on Windows, optionally followed by options
+    /// such as `,nosuspend`), which is how `dotnet-trace`,
+    /// `dotnet-counters` and other out-of-process tools attach to it.
+    /// Requires .NET 5 or later. Ports are only configured through
+    /// `DOTNET_DiagnosticPorts`, which is set for the duration of
+    /// `build()` and restored afterwards. Can be called more than once.
+    pub fn with_diagnostic_port<T: Into<String>>(&mut self, address: T) -> &mut ClrHostBuilder {
+        self.diagnostic_ports.push(address.into());
+        self
+    }
 
-        let tpa = try!(build_tpas(&actual_path.clone()));
+    /// Controls whether precompiled ReadyToRun code is used. The runtime
+    /// only reads this from the `DOTNET_ReadyToRun` environment variable
+    /// (.NET 6 or later; older runtimes use `COMPlus_ReadyToRun`), so it
+    /// is set for the duration of `build()` and restored afterwards.
+    pub fn with_ready_to_run(&mut self, enabled: bool) -> &mut ClrHostBuilder {
+        self.ready_to_run = Some(enabled);
+        self
+    }
 
-        let assembly = CString::new(assembly_path).unwrap();
-        let name = if let Some(ref s) = self.appdomain_name {
-            try!(CString::new(s.clone()))
-        } else {
-            CString::new("rust_coreclr_host").unwrap()
-        };
+    /// Loads libcoreclr with `RTLD_GLOBAL`, so that native libraries the
+    /// runtime later P/Invokes into can see its symbols. Has no effect on
+    /// Windows.
+    pub fn with_global_symbols(&mut self) -> &mut ClrHostBuilder {
+        self.global_symbols = true;
+        self
+    }
 
-        let server_gc = if self.server_gc {
-            CString::new("true").unwrap()
-        } else {
-            CString::new("false").unwrap()
-        };
+    /// Loads libcoreclr with `RTLD_LAZY` instead of `RTLD_NOW`, so symbols
+    /// are bound on first use rather than all at once. This can get the
+    /// runtime loading on minimal systems where some unrelated dependency
+    /// is missing, but a call that needs it will then crash the process
+    /// instead of failing cleanly in `build`. Has no effect on Windows.
+    pub fn with_lazy_binding(&mut self) -> &mut ClrHostBuilder {
+        self.load_mode = loader::LoadMode::Lazy;
+        self
+    }
 
-        let concurrent_gc = if self.concurrent_gc {
-            CString::new("true").unwrap()
-        } else {
-            CString::new("false").unwrap()
-        };
+    /// Loads a native library (e.g. `libclrjit`) with `RTLD_GLOBAL` before
+    /// coreclr itself, for environments where the runtime can't find its
+    /// companions on its own. The library stays loaded for the lifetime
+    /// of the host.
+    pub fn with_preload_library<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
+        self.preload_libraries.push(path.into());
+        self
+    }
 
-        let property_keys = vec![
-            CString::new("TRUSTED_PLATFORM_ASSEMBLIES").unwrap(),
-            CString::new("APP_PATHS").unwrap(),
-            CString::new("APP_NI_PATHS").unwrap(),
-            CString::new("NATIVE_DLL_SEARCH_DIRECTORIES").unwrap(),
-            CString::new("AppDomainCompatSwitch").unwrap(),
-            CString::new("System.GC.Server").unwrap(),
-            CString::new("System.GC.Concurrent").unwrap()
-        ];
+    /// Sets the directory holding coreclr and the framework assemblies. A
+    /// path to the coreclr library itself is accepted too, and treated as
+    /// if it had been given to `with_coreclr_library_file`.
+    pub fn with_coreclr_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
+        self.coreclr_path = Some(path.into());
+        self
+    }
 
-        let property_values = vec![
-            CString::new(tpa).unwrap(),
-            CString::new(probe_paths.clone()).unwrap(),
-            CString::new(probe_paths).unwrap(),
-            CString::new(native_search_path).unwrap(),
-            CString::new("UseLatestBehaviorWhenTFMNotSpecified").unwrap(),
-            server_gc,
-            concurrent_gc
-        ];
+    /// Applies an app's `.runtimeconfig.json` when the host is built. Its
+    /// `configProperties` become runtime properties, taking precedence
+    /// over settings made through the `with_*` methods but not over
+    /// `with_property`. If no coreclr path is set, the runtime is found as
+    /// with `with_dotnet_runtime`, from the `Microsoft.NETCore.App`
+    /// version the file names.
+    pub fn with_runtime_config<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
+        self.runtime_config = Some(path.into());
+        self
+    }
 
-        assert!(property_keys.len() == property_values.len());
+    /// Points the builder at a machine-wide .NET install, picking the
+    /// highest installed runtime whose version starts with `version`.
+    /// If nothing suitable is found, `build()` reports which directories
+    /// were searched (unless a coreclr path is set some other way).
+    pub fn with_dotnet_runtime(&mut self, version: Option<&str>) -> &mut ClrHostBuilder {
+        match discovery::find_runtime(version) {
+            Ok(path) => {
+                self.coreclr_path = Some(path);
+                self.runtime_search_error = None;
+            }
+            Err(msg) => self.runtime_search_error = Some(msg)
+        }
 
-        // initialize!
-        let mut property_keys_raw : Vec<_> = property_keys.iter().map(|p| p.as_ptr()).collect();
-        let mut property_values_raw : Vec<_> = property_values.iter().map(|p| p.as_ptr()).collect();
+        self
+    }
 
-        let mut handle : *mut libc::c_void = std::ptr::null_mut();
-        let mut domain_id : libc::c_uint = 0;
-        let result = (functions.initialize)(
-            assembly.as_ptr(),
-            name.as_ptr(),
-            property_keys.len() as libc::c_int,
-            property_keys_raw.as_mut_ptr(),
-            property_values_raw.as_mut_ptr(),
-            &mut handle as *mut _,
+    /// Loads coreclr from exactly this file, for builds whose library has
+    /// a nonstandard name. The file's directory is used wherever the
+    /// coreclr path would be, and this takes precedence over
+    /// `with_coreclr_path` if both are set.
+    pub fn with_coreclr_library_file<T: Into<PathBuf>>(&mut self, file: T) -> &mut ClrHostBuilder {
+        self.coreclr_library_file = Some(file.into());
+        self
+    }
+
+    /// Looks for the coreclr library under this file name in the coreclr
+    /// path, instead of the platform's usual one, for runtimes that were
+    /// repackaged with e.g. a versioned `libcoreclr-6.0.so`.
+    pub fn with_coreclr_filename<T: Into<String>>(&mut self, name: T) -> &mut ClrHostBuilder {
+        self.coreclr_filename = Some(name.into());
+        self
+    }
+
+    /// If the coreclr library isn't directly in the coreclr path, looks
+    /// for it in the directories up to two levels below and uses the
+    /// first one found, to smooth over small layout differences between
+    /// SDK versions. Without this, the library has to be right there.
+    pub fn with_coreclr_autodiscover(&mut self) -> &mut ClrHostBuilder {
+        self.coreclr_autodiscover = true;
+        self
+    }
+
+    /// Sets up the builder for a self-contained publish, where coreclr,
+    /// the framework and the app all live in `app_dir`. That directory
+    /// becomes the coreclr path and an assembly probe path. Unless
+    /// `with_assembly` is also called, the app's own `.dll` is used as the
+    /// assembly, found through its `.runtimeconfig.json`.
+    pub fn with_self_contained_app<T: Into<PathBuf>>(&mut self, app_dir: T) -> &mut ClrHostBuilder {
+        let app_dir = app_dir.into();
+        self.coreclr_path = Some(app_dir.clone());
+        self.runtime_search_error = None;
+        self.assembly_load_paths.push(app_dir.clone());
+        self.self_contained_app = Some(app_dir);
+        self
+    }
+
+    pub fn with_assembly_probe_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
+        self.assembly_load_paths.push(path.into());
+        self
+    }
+
+    /// Adds the directory of the main assembly to the assembly probe path
+    /// when the host is built, unless it's already there, so the app's
+    /// own dependencies can be found without a separate
+    /// `with_assembly_probe_path` call.
+    pub fn with_assembly_directory_on_probe_path(&mut self) -> &mut ClrHostBuilder {
+        self.assembly_directory_on_probe_path = true;
+        self
+    }
+
+    /// Adds a directory to `APP_NI_PATHS`, where the runtime looks for
+    /// precompiled native images. Without any, the assembly probe paths
+    /// are used.
+    pub fn with_native_image_probe_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
+        self.native_image_probe_paths.push(path.into());
+        self
+    }
+
+    pub fn with_native_library_probe_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
+        self.native_library_search_paths.push(path.into());
+        self
+    }
+
+    /// Uses an already joined list of directories, such as one computed by
+    /// another launcher, as `NATIVE_DLL_SEARCH_DIRECTORIES`, with only the
+    /// coreclr directory put in front. It replaces any native library
+    /// probe paths, and isn't checked, so it's up to the caller to separate
+    /// entries with `PATH_LIST_SEP`.
+    pub fn with_native_search_directories_raw<T: Into<String>>(&mut self, value: T) -> &mut ClrHostBuilder {
+        self.native_search_directories_raw = Some(value.into());
+        self
+    }
+
+    /// Adds every directory listed in the environment variable `var`
+    /// (separated like PATH) as a native library probe path. Entries that
+    /// don't exist are skipped, as is a variable that isn't set.
+    pub fn with_native_search_from_env(&mut self, var: &str) -> &mut ClrHostBuilder {
+        if let Some(value) = env::var_os(var) {
+            for dir in env::split_paths(&value).filter(|d| d.is_dir()) {
+                self.native_library_search_paths.push(dir);
+            }
+        }
+
+        self
+    }
+
+    pub fn with_assembly<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
+        self.assembly = Some(path.into());
+        self
+    }
+
+    pub fn with_appdomain_name<T: Into<String>>(&mut self, name: T) -> &mut ClrHostBuilder {
+        self.appdomain_name = Some(name.into());
+        self
+    }
+
+    /// Overrides the `AppDomainCompatSwitch` property, which otherwise is
+    /// `UseLatestBehaviorWhenTFMNotSpecified`.
+    pub fn with_appdomain_compat_switch<T: Into<String>>(&mut self, value: T) -> &mut ClrHostBuilder {
+        self.appdomain_compat_switch = Some(value.into());
+        self
+    }
+
+    /// Runs the runtime in globalization-invariant mode, so it starts on
+    /// systems without ICU (e.g. minimal container images). All cultures
+    /// then behave like the invariant culture: culture-sensitive formatting
+    /// and string comparison become ordinal-like, and on .NET 6 and later
+    /// creating any culture other than the invariant one throws.
+    pub fn with_invariant_globalization(&mut self) -> &mut ClrHostBuilder {
+        self.invariant_globalization = true;
+        self
+    }
+
+    /// Sets what `AppContext.BaseDirectory` returns in managed code. By
+    /// default it's the directory of the assembly given to `with_assembly`.
+    pub fn with_app_context_base_directory<T: Into<PathBuf>>(&mut self, dir: T) -> &mut ClrHostBuilder {
+        self.app_context_base_directory = Some(dir.into());
+        self
+    }
+
+    /// Registers a startup hook assembly, whose `StartupHook.Initialize`
+    /// runs before the managed entry point. May be called more than once;
+    /// the hooks run in the order they were added.
+    pub fn with_startup_hook<P: Into<PathBuf>>(&mut self, assembly: P) -> &mut ClrHostBuilder {
+        self.startup_hooks.push(assembly.into());
+        self
+    }
+
+    /// Runs executed assemblies with `dir` as the current directory. The
+    /// process's current directory is changed just before the assembly
+    /// starts and put back once it returns; since that's process-global
+    /// state, other threads will see the change while it runs.
+    pub fn with_working_directory<T: Into<PathBuf>>(&mut self, dir: T) -> &mut ClrHostBuilder {
+        self.working_directory = Some(dir.into());
+        self
+    }
+
+    /// Redirects the process's stdout and stderr into pipes for the
+    /// lifetime of the host, so that `ClrHost::execute_assembly_captured`
+    /// can return what the managed program printed. Note that this
+    /// captures everything written to those streams, Rust output included.
+    pub fn with_captured_output(&mut self) -> &mut ClrHostBuilder {
+        self.captured_output = true;
+        self
+    }
+
+    /// Sends diagnostic messages about how the host is being set up (TPA
+    /// entries, resolved symbols, the final runtime properties), and when
+    /// the runtime is shut down, to `logger`. Nothing is formatted when no
+    /// logger is set.
+    pub fn with_logger(&mut self, logger: Box<dyn Fn(&str) + Send + Sync>) -> &mut ClrHostBuilder {
+        self.logger = Some(logging::Logger::new(logger));
+        self
+    }
+
+    pub fn with_property<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> &mut ClrHostBuilder {
+        self.properties.push((key.into(), value.into()));
+        self
+    }
+
+    /// Adds every pair in `props` as if by `with_property`. Later entries
+    /// override earlier ones with the same key.
+    pub fn with_properties<I: IntoIterator<Item = (String, String)>>(&mut self, props: I) -> &mut ClrHostBuilder {
+        self.properties.extend(props);
+        self
+    }
+
+    /// Adds a single assembly that lives outside the coreclr directory to
+    /// the trusted platform assembly list. It must be a .dll or .exe; a
+    /// file whose name is already on the list is skipped.
+    pub fn with_trusted_platform_assembly<T: Into<PathBuf>>(&mut self, path: T) -> &mut ClrHostBuilder {
+        self.trusted_platform_assemblies.push(path.into());
+        self
+    }
+
+    /// Makes the trusted platform assembly scan descend into
+    /// subdirectories of the coreclr path instead of only looking at
+    /// the top level.
+    pub fn with_recursive_tpa(&mut self) -> &mut ClrHostBuilder {
+        self.recursive_tpa = true;
+        self
+    }
+
+    /// Puts these files at the front of the trusted platform assembly list,
+    /// in the order given. Each entry is either a file name such as
+    /// `System.Private.CoreLib.dll` (matched ignoring case) or a full path.
+    /// A prioritized file also wins over other files with the same
+    /// assembly name, so a full path picks which copy the runtime binds to.
+    pub fn with_tpa_priority(&mut self, files: &[&str]) -> &mut ClrHostBuilder {
+        self.tpa_priority = files.iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    /// Replaces the file extensions (`dll` and `exe` by default) that the
+    /// trusted platform assembly scan picks up. Multi-part extensions such
+    /// as `ni.dll` work, and matching ignores case.
+    pub fn with_tpa_extensions(&mut self, exts: &[&str]) -> &mut ClrHostBuilder {
+        self.tpa_extensions = exts.iter()
+            .map(|e| e.trim_start_matches('.').to_lowercase())
+            .collect();
+        self
+    }
+
+    /// Returns the `PATH_LIST_SEP`-delimited trusted platform assembly list that
+    /// `build()` would pass to the runtime, without starting it.
+    pub fn compute_tpa(&self) -> Result<String, ClrError> {
+        join_path_string(&self.discover_assemblies()?)
+    }
+
+    /// The assemblies that make up the trusted platform assembly list, in
+    /// order and deduplicated, exactly as `build()` would pass them. The
+    /// string from `compute_tpa` is these joined with `PATH_LIST_SEP`.
+    pub fn discover_assemblies(&self) -> Result<Vec<PathBuf>, ClrError> {
+        match self.coreclr_directory() {
+            Some(ref path) => {
+                for file in &self.trusted_platform_assemblies {
+                    if !is_assembly(file, &self.tpa_extensions) {
+                        return Err(Error::new(ErrorKind::InvalidInput,
+                            format!("{} does not have one of the TPA extensions ({})",
+                                file.display(), self.tpa_extensions.join(", "))).into());
+                    }
+                }
+
+                Ok(build_tpas(path,
+                    self.recursive_tpa,
+                    &self.trusted_platform_assemblies,
+                    &self.tpa_extensions,
+                    &self.tpa_priority,
+                    self.logger.as_ref())?)
+            }
+            None => Err(Error::new(ErrorKind::NotFound, "no path to coreclr provided").into())
+        }
+    }
+
+    // the directory holding coreclr and the framework assemblies. an
+    // explicit library file wins over with_coreclr_path.
+    fn coreclr_directory(&self) -> Option<PathBuf> {
+        match self.coreclr_library_file.as_deref().or(self.coreclr_path_library_file()) {
+            Some(file) => Some(file.parent().map(|p| p.to_path_buf()).unwrap_or_default()),
+            None => self.coreclr_path.clone()
+        }
+    }
+
+    // with_coreclr_path is easily given the library rather than its
+    // directory, so a file with the library's name is taken to be that.
+    fn coreclr_path_library_file(&self) -> Option<&Path> {
+        self.coreclr_path.as_deref()
+            .filter(|p| p.is_file() && p.file_name().is_some_and(|n| n == self.coreclr_library_name()))
+    }
+
+    fn coreclr_library_name(&self) -> &str {
+        if let Some(ref name) = self.coreclr_filename {
+            name.as_str()
+        } else if cfg!(target_os = "macos") {
+            "libcoreclr.dylib"
+        } else if cfg!(target_os = "linux") {
+            "libcoreclr.so"
+        } else {
+            "coreclr.dll"
+        }
+    }
+
+    /// Like `build`, but also returns how long it took to load coreclr and
+    /// initialize the runtime, for measuring cold start.
+    ///
+    /// CoreCLR can't be initialized again in a process once it has been
+    /// shut down, and it no longer supports creating additional AppDomains,
+    /// so repeated measurements each need a fresh process.
+    pub fn build_measured(&self) -> Result<(ClrHost, Duration), ClrError> {
+        let start = Instant::now();
+        let host = self.build()?;
+        Ok((host, start.elapsed()))
+    }
+
+    /// Runs every check `build` does before it loads coreclr, including
+    /// finding the TPA, without loading or initializing anything.
+    pub fn validate(&self) -> Result<(), ClrError> {
+        self.prepare().map(|_| ())
+    }
+
+    /// The properties `build` would hand to `coreclr_initialize`, in order
+    /// and exactly as they'd be sent, for bug reports and the like. Nothing
+    /// is loaded or initialized.
+    pub fn effective_properties(&self) -> Result<Vec<(String, String)>, ClrError> {
+        let prepared = self.prepare()?;
+        Ok(prepared.properties.iter()
+            .map(|(key, value)| (key.to_string_lossy().into_owned(), value.to_string_lossy().into_owned()))
+            .collect())
+    }
+
+    pub fn build(&self) -> Result<ClrHost, ClrError> {
+        self.start(self.prepare()?)
+    }
+
+    /// Starts the runtime in a child process instead of this one, so that
+    /// several differently configured runtimes can be used at once. Only
+    /// `execute_assembly` is proxied; see `RemoteClrHost`.
+    ///
+    /// The child is this same executable, run again. Its `main` must call
+    /// `serve_remote_host` before doing anything else, which is where the
+    /// runtime is started and requests get answered.
+    ///
+    /// The configuration is checked here, but the logger and output
+    /// capture don't carry over to the child, whose output goes wherever
+    /// this process's does.
+    #[cfg(unix)]
+    pub fn build_out_of_process(&self) -> Result<RemoteClrHost, ClrError> {
+        RemoteClrHost::spawn(self, &self.prepare()?)
+    }
+
+    fn start(&self, prepared: PreparedHost) -> Result<ClrHost, ClrError> {
+        let PreparedHost { coreclr_path, assembly, name, properties, assembly_load_paths } = prepared;
+
+        // the embedded helper went on the TPA list in prepare, but is only
+        // written out now, so that validate and effective_properties don't
+        // touch the disk.
+        helper::write_embedded()?;
+
+        // companions go first and are global, so that coreclr can find
+        // their symbols when it loads.
+        let mut preloaded = vec![];
+        for path in &self.preload_libraries {
+            let mut library = load_library(path, true, self.load_mode)
+                .map_err(|err| ClrError::LibraryLoad(Error::new(err.kind(),
+                    format!("could not preload {}: {}", path.display(), err))))?;
+            library.set_logger(self.logger.clone());
+            preloaded.push(library);
+        }
+
+        let mut lib = load_library(&coreclr_path, self.global_symbols, self.load_mode).map_err(ClrError::LibraryLoad)?;
+        lib.set_logger(self.logger.clone());
+        let logger = self.logger.as_ref();
+        // missing any of the mandatory exports means it's some other
+        // library entirely (libhostpolicy, say), which the bare symbol
+        // name doesn't make clear.
+        let not_coreclr = |err| match err {
+            ClrError::SymbolNotFound(name) => Error::new(ErrorKind::InvalidData,
+                format!("{} does not export {}; is this really libcoreclr?", coreclr_path.display(), name)).into(),
+            err => err
+        };
+
+        // load our function pointers.
+        let functions = unsafe {
+            ClrFunctions {
+                initialize: resolve(&lib, "coreclr_initialize", logger).map_err(not_coreclr)?,
+                shutdown: resolve(&lib, "coreclr_shutdown", logger).map_err(not_coreclr)?,
+                shutdown_2: resolve(&lib, "coreclr_shutdown_2", logger).ok(),
+                create_delegate: resolve(&lib, "coreclr_create_delegate", logger).map_err(not_coreclr)?,
+                execute_assembly: resolve(&lib, "coreclr_execute_assembly", logger).map_err(not_coreclr)?
+            }
+        };
+
+        // older runtimes don't have this, in which case crashes only
+        // report their exit code.
+        if let Ok(set_error_writer) = unsafe { resolve::<SetErrorWriterFn>(&lib, "coreclr_set_error_writer", logger) } {
+            set_error_writer(Some(error_writer));
+        }
+
+        for (key, value) in &properties {
+            logging::log(logger, || format!("property {}={}", key.to_string_lossy(), value.to_string_lossy()));
+        }
+
+        // initialize!
+        let mut property_keys_raw : Vec<_> = properties.iter().map(|p| p.0.as_ptr()).collect();
+        let mut property_values_raw : Vec<_> = properties.iter().map(|p| p.1.as_ptr()).collect();
+
+        if RUNTIME_ACTIVE.swap(true, Ordering::SeqCst) {
+            return Err(runtime_unavailable());
+        }
+
+        // the managed Console grabs the standard handles when it's first
+        // used, so they need to be redirected before the runtime starts.
+        let output_capture = if self.captured_output {
+            match capture::OutputCapture::start() {
+                Ok(capture) => Some(capture),
+                Err(err) => {
+                    RUNTIME_ACTIVE.store(false, Ordering::SeqCst);
+                    return Err(err.into());
+                }
+            }
+        } else {
+            None
+        };
+
+        let env_guard = EnvGuard::set(&self.startup_env_vars());
+        let mut handle : *mut c_void = std::ptr::null_mut();
+        let mut domain_id : c_uint = 0;
+        let result = (functions.initialize)(
+            assembly.as_ptr(),
+            name.as_ptr(),
+            properties.len() as c_int,
+            property_keys_raw.as_mut_ptr(),
+            property_values_raw.as_mut_ptr(),
+            &mut handle as *mut _,
             &mut domain_id as *mut _
         );
 
+        drop(env_guard);
         if result != 0 {
-            Err(Error::from_raw_os_error(result))
+            RUNTIME_ACTIVE.store(false, Ordering::SeqCst);
+            Err(ClrError::Initialize(result))
+        } else if handle.is_null() {
+            // seen with mismatched coreclr and System.Private.CoreLib
+            // builds. every later call would dereference the handle.
+            RUNTIME_ACTIVE.store(false, Ordering::SeqCst);
+            Err(Error::other("coreclr_initialize returned success but gave a null host handle").into())
         } else {
             Ok(ClrHost {
                 coreclr: lib,
+                preloaded,
                 coreclr_funs: functions,
                 coreclr_handle: handle as *mut _,
-                domain_id: domain_id as usize
+                domain_id,
+                shutdown_done: false,
+                output_capture,
+                assembly_load_paths,
+                executed: AtomicBool::new(false),
+                delegate_cache: HashMap::new(),
+                working_directory: self.working_directory.clone(),
+                coreclr_library_path: coreclr_path,
+                logger: self.logger.clone()
             })
         }
     }
+
+    // some knobs are only ever read from the environment, and only while
+    // the runtime starts up.
+    fn startup_env_vars(&self) -> Vec<(&'static str, String)> {
+        let flag = |enabled| if enabled { "1".to_string() } else { "0".to_string() };
+        let mut env_vars = vec![];
+        if let Some(enabled) = self.ready_to_run {
+            env_vars.push(("DOTNET_ReadyToRun", flag(enabled)));
+        }
+
+        if let Some(enabled) = self.on_stack_replacement {
+            env_vars.push(("DOTNET_TC_OnStackReplacement", flag(enabled)));
+        }
+
+        if !self.diagnostic_ports.is_empty() {
+            env_vars.push(("DOTNET_DiagnosticPorts", self.diagnostic_ports.join(";")));
+        }
+
+        env_vars
+    }
+
+    // everything `build` works out from the configuration before it
+    // touches coreclr.
+    fn prepare(&self) -> Result<PreparedHost, ClrError> {
+        // a runtime config is folded into a copy of the builder, which
+        // then prepares as if it had been configured that way.
+        if let Some(ref path) = self.runtime_config {
+            let config = runtime_config::read(path)?;
+            let mut resolved = self.clone();
+            resolved.runtime_config = None;
+            resolved.runtime_config_properties = config.properties;
+            if let Some(version) = config.framework_version {
+                if resolved.coreclr_directory().is_none() {
+                    // patch releases roll forward, so any x.y.* will do.
+                    let major_minor : Vec<&str> = version.splitn(3, '.').take(2).collect();
+                    resolved.with_dotnet_runtime(Some(&major_minor.join(".")));
+                }
+            }
+
+            return resolved.prepare();
+        }
+
+        let coreclr_dir = self.coreclr_directory();
+        let coreclr_path = if let Some(ref p) = coreclr_dir {
+            if !p.is_dir() {
+                return Err(Error::new(ErrorKind::NotFound,
+                    format!("coreclr path {} does not exist or is not a directory", p.display())).into());
+            }
+
+            path_bytes(p)?
+        } else if let Some(ref msg) = self.runtime_search_error {
+            return Err(Error::new(ErrorKind::NotFound, msg.clone()).into());
+        } else {
+            return Err(Error::new(ErrorKind::NotFound, "no path to coreclr provided").into());
+        };
+
+        if let Some(percent) = self.gc_heap_hard_limit_percent {
+            if percent == 0 || percent > 100 {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                    format!("GC heap hard limit percent must be between 1 and 100, got {}", percent)).into());
+            }
+        }
+
+        if !self.server_gc && (self.gc_heap_count.is_some() || self.gc_no_affinitize.is_some()) {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                "the GC heap count and affinitization only apply to server GC, but workstation GC is selected").into());
+        }
+
+        if self.gc_heap_count == Some(0) {
+            return Err(Error::new(ErrorKind::InvalidInput, "GC heap count must be at least 1").into());
+        }
+
+        if let (Some(min), Some(max)) = (self.threadpool_min_threads, self.threadpool_max_threads) {
+            if min > max {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                    format!("thread pool minimum of {} threads is above the maximum of {}", min, max)).into());
+            }
+        }
+
+        if let Some(ref dir) = self.working_directory {
+            if !dir.is_dir() {
+                return Err(Error::new(ErrorKind::NotFound,
+                    format!("working directory {} does not exist", dir.display())).into());
+            }
+        }
+
+        for hook in &self.startup_hooks {
+            if !hook.is_file() {
+                return Err(Error::new(ErrorKind::NotFound,
+                    format!("startup hook {} does not exist", hook.display())).into());
+            }
+        }
+
+        let startup_hooks = join_path_list(&self.startup_hooks)?;
+
+        // hosts that only ever create delegates don't have a main assembly,
+        // so in that case the runtime is told that coreclr's own directory
+        // is the "executable" path.
+        let app_assembly = match (self.assembly.as_ref(), self.self_contained_app.as_ref()) {
+            (Some(assembly), _) => Some(absolute_assembly_path(assembly)?),
+            (None, Some(dir)) => find_app_assembly(dir).map(|p| absolute_assembly_path(&p)).transpose()?,
+            _ => None
+        };
+
+        let assembly_path = if let Some(ref p) = app_assembly {
+            path_bytes(p)?
+        } else {
+            coreclr_path.clone()
+        };
+
+        // AppContext.BaseDirectory, which managed code expects to end in a
+        // separator, like the dotnet host leaves it.
+        let base_directory = match self.app_context_base_directory {
+            Some(ref dir) => Some(dir.clone()),
+            None => app_assembly.as_ref()
+                .and_then(|p| p.parent())
+                .map(|p| p.to_path_buf())
+        };
+
+        let base_directory = match base_directory {
+            Some(ref dir) if !dir.as_os_str().is_empty() => {
+                let mut bytes = path_bytes(dir)?;
+                if !bytes.ends_with(MAIN_SEPARATOR_STR.as_bytes()) {
+                    bytes.extend_from_slice(MAIN_SEPARATOR_STR.as_bytes());
+                }
+
+                Some(bytes)
+            }
+            _ => None
+        };
+
+        // every list expected by the runtime here is delimited by PATH_LIST_SEP.
+
+        // first - building native search directory paths.
+        // by default, the directory where libcoreclr resides is
+        // probed by the runtime for PInvoke targets.
+        let mut native_search_path = coreclr_path.clone();
+        let native_search_rest = match self.native_search_directories_raw {
+            Some(ref raw) => {
+                if !self.native_library_search_paths.is_empty() {
+                    logging::log(self.logger.as_ref(), || "native library probe paths are ignored, \
+                        since with_native_search_directories_raw replaces them".to_string());
+                }
+
+                raw.as_bytes().to_vec()
+            }
+            None => join_path_list(&self.native_library_search_paths)?
+        };
+
+        if !native_search_rest.is_empty() {
+            native_search_path.push(PATH_LIST_SEP as u8);
+            native_search_path.extend(native_search_rest);
+        }
+
+        // second - find coreclr.
+        let coreclr_path = if let Some(ref file) = self.coreclr_library_file {
+            file.clone()
+        } else if let Some(file) = self.coreclr_path_library_file() {
+            logging::log(self.logger.as_ref(), || format!("coreclr path {} is the coreclr library, not a directory; \
+                using the directory it's in", file.display()));
+            file.to_path_buf()
+        } else {
+            let coreclr_dir = coreclr_dir.unwrap();
+            let library_name = self.coreclr_library_name();
+
+            let coreclr_path = coreclr_dir.join(library_name);
+            if !coreclr_path.is_file() && self.coreclr_autodiscover {
+                find_file_below(&coreclr_dir, library_name, 2).ok_or_else(|| Error::new(ErrorKind::NotFound,
+                    format!("no {} found in or below {}", library_name, coreclr_dir.display())))?
+            } else {
+                coreclr_path
+            }
+        };
+
+        if !coreclr_path.is_file() {
+            return Err(Error::new(ErrorKind::NotFound,
+                format!("coreclr library {} does not exist", coreclr_path.display())).into());
+        }
+
+        // build up CStrings to send to coreclr.
+        let mut assembly_load_paths = self.assembly_load_paths.clone();
+        if self.assembly_directory_on_probe_path {
+            if let Some(dir) = app_assembly.as_ref().and_then(|p| p.parent()) {
+                if !assembly_load_paths.iter().any(|p| canonicalize_or_raw(p) == dir) {
+                    assembly_load_paths.push(dir.to_path_buf());
+                }
+            }
+        }
+
+        let probe_paths = join_path_list(&assembly_load_paths)?;
+        // native images are looked for alongside the IL unless they were
+        // given a home of their own.
+        let ni_paths = if self.native_image_probe_paths.is_empty() {
+            probe_paths.clone()
+        } else {
+            join_path_list(&self.native_image_probe_paths)?
+        };
+
+        let mut tpa_paths = self.discover_assemblies()?;
+        if tpa_paths.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound,
+                format!("no trusted platform assemblies found under {}; is this a CoreCLR directory?",
+                    self.coreclr_directory().unwrap_or_default().display())).into());
+        }
+
+        // a helper the user supplied themselves takes precedence.
+        if let Some(helper) = helper::embedded_path() {
+            if !tpa_paths.iter().any(|p| p.file_stem() == helper.file_stem()) {
+                tpa_paths.push(helper);
+            }
+        }
+
+        let tpa = join_path_list(&tpa_paths)?;
+
+        let assembly = c_string(assembly_path, || "the assembly path".to_string())?;
+        let name = if let Some(ref s) = self.appdomain_name {
+            if s.is_empty() || s.contains('\0') {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                    format!("invalid appdomain name {:?}: it must be non-empty and contain no NUL bytes", s)).into());
+            }
+
+            CString::new(s.clone()).unwrap()
+        } else {
+            CString::new("rust_coreclr_host").unwrap()
+        };
+
+        let compat_switch = match self.appdomain_compat_switch {
+            Some(ref value) => c_string(value.as_str(), || "the AppDomain compat switch".to_string())?,
+            None => CString::new("UseLatestBehaviorWhenTFMNotSpecified").unwrap()
+        };
+
+        let server_gc = if self.server_gc {
+            CString::new("true").unwrap()
+        } else {
+            CString::new("false").unwrap()
+        };
+
+        let concurrent_gc = if self.concurrent_gc {
+            CString::new("true").unwrap()
+        } else {
+            CString::new("false").unwrap()
+        };
+
+        // keys and values are kept paired so they can't get out of step.
+        let mut properties = vec![
+            (CString::new("TRUSTED_PLATFORM_ASSEMBLIES").unwrap(), c_string(tpa, || "a TPA path".to_string())?),
+            (CString::new("APP_PATHS").unwrap(), c_string(probe_paths, || "an assembly probe path".to_string())?),
+            (CString::new("APP_NI_PATHS").unwrap(), c_string(ni_paths, || "a native image probe path".to_string())?),
+            (CString::new("NATIVE_DLL_SEARCH_DIRECTORIES").unwrap(),
+                c_string(native_search_path, || "a native library probe path".to_string())?),
+            (CString::new("AppDomainCompatSwitch").unwrap(), compat_switch),
+            (CString::new("System.GC.Server").unwrap(), server_gc),
+            (CString::new("System.GC.Concurrent").unwrap(), concurrent_gc)
+        ];
+
+        // the runtime parses these limits as hex.
+        if let Some(bytes) = self.gc_heap_hard_limit {
+            properties.push((CString::new("System.GC.HeapHardLimit").unwrap(),
+                CString::new(format!("0x{:X}", bytes)).unwrap()));
+        }
+
+        if let Some(percent) = self.gc_heap_hard_limit_percent {
+            properties.push((CString::new("System.GC.HeapHardLimitPercent").unwrap(),
+                CString::new(format!("0x{:X}", percent)).unwrap()));
+        }
+
+        if let Some(count) = self.gc_heap_count {
+            properties.push((CString::new("System.GC.HeapCount").unwrap(),
+                CString::new(count.to_string()).unwrap()));
+        }
+
+        if let Some(no_affinitize) = self.gc_no_affinitize {
+            properties.push((CString::new("System.GC.NoAffinitize").unwrap(),
+                CString::new(no_affinitize.to_string()).unwrap()));
+        }
+
+        if let Some(enabled) = self.tiered_compilation {
+            properties.push((CString::new("System.Runtime.TieredCompilation").unwrap(),
+                CString::new(enabled.to_string()).unwrap()));
+        }
+
+        if let Some(enabled) = self.tiered_pgo {
+            properties.push((CString::new("System.Runtime.TieredPGO").unwrap(),
+                CString::new(enabled.to_string()).unwrap()));
+        }
+
+        if let Some(threads) = self.threadpool_min_threads {
+            properties.push((CString::new("System.Threading.ThreadPool.MinThreads").unwrap(),
+                CString::new(threads.to_string()).unwrap()));
+        }
+
+        if let Some(threads) = self.threadpool_max_threads {
+            properties.push((CString::new("System.Threading.ThreadPool.MaxThreads").unwrap(),
+                CString::new(threads.to_string()).unwrap()));
+        }
+
+        if let Some(enabled) = self.quick_jit {
+            properties.push((CString::new("System.Runtime.TieredCompilation.QuickJit").unwrap(),
+                CString::new(enabled.to_string()).unwrap()));
+        }
+
+        if let Some(enabled) = self.quick_jit_for_loops {
+            properties.push((CString::new("System.Runtime.TieredCompilation.QuickJitForLoops").unwrap(),
+                CString::new(enabled.to_string()).unwrap()));
+        }
+
+        if let Some(dir) = base_directory {
+            properties.push((CString::new("APP_CONTEXT_BASE_DIRECTORY").unwrap(), c_string(dir, || "the app context base directory".to_string())?));
+        }
+
+        if self.invariant_globalization {
+            properties.push((CString::new("System.Globalization.Invariant").unwrap(), CString::new("true").unwrap()));
+        }
+
+        if !startup_hooks.is_empty() {
+            properties.push((CString::new("STARTUP_HOOKS").unwrap(), c_string(startup_hooks, || "a startup hook path".to_string())?));
+        }
+
+        // properties from a runtime config and then the user's own go after
+        // the built-in ones, unless they name a key already there, in which
+        // case the later value wins.
+        for (key, value) in self.runtime_config_properties.iter().chain(&self.properties) {
+            let key = c_string(key.as_str(), || format!("property name {:?}", key))?;
+            let value = c_string(value.as_str(), || format!("the value of property {:?}", key))?;
+            if let Some(existing) = properties.iter_mut().find(|p| p.0 == key) {
+                existing.1 = value;
+            } else {
+                properties.push((key, value));
+            }
+        }
+
+        Ok(PreparedHost {
+            coreclr_path,
+            assembly,
+            name,
+            properties,
+            assembly_load_paths
+        })
+    }
+}
+
+/// Builds a trusted platform assembly list the same way `build()` does by
+/// default: every `.dll` and `.exe` directly inside `dir`, keeping only the
+/// first file seen for each assembly name, joined with `PATH_LIST_SEP`.
+///
+/// `build()` doesn't call this, since it applies the builder's TPA
+/// settings on top and needs the paths kept apart, but both run the same
+/// scan; `ClrHostBuilder::discover_assemblies` is the builder's version.
+pub fn build_tpa_list(dir: &Path) -> Result<String, ClrError> {
+    let extensions : Vec<String> = DEFAULT_TPA_EXTENSIONS.iter().map(|e| e.to_string()).collect();
+    join_path_string(&build_tpas(dir, false, &[], &extensions, &[], None)?)
 }
 
-fn build_tpas(path: &Path) -> io::Result<String> {
+fn build_tpas(path: &Path,
+    recursive: bool,
+    extra: &[PathBuf],
+    extensions: &[String],
+    priority: &[String],
+    logger: Option<&logging::Logger>) -> io::Result<Vec<PathBuf>> {
+    let mut candidates = vec![];
+    collect_tpas(path, recursive, extensions, &mut candidates)?;
+    candidates.extend(extra.iter().cloned());
+    // this runs before deduplication, so a prioritized file is also the
+    // copy that wins. the sort is stable, so everything else keeps its order.
+    if !priority.is_empty() {
+        candidates.sort_by_key(|file| tpa_priority(file, priority));
+    }
+
     let mut buffer = vec![];
     let mut set = HashSet::new();
-    for file in try!(fs::read_dir(path)) {
-        let actual_file = try!(file);
-        if let Some(p) = actual_file.path().extension() {
-            if let Some(s) = p.to_str() {
-                match s {
-                    "dll" | "exe" => {
-                        let file = actual_file.path().clone();
-
-                        // don't want to insert duplicates
-                        if set.insert(file.clone()) {
-                            buffer.push(actual_file
-                                .path()
-                                .to_str()
-                                .unwrap()
-                                .to_string());
-                        }
-                    }
-                    _ => {}
-                }
+    for file in &candidates {
+        add_tpa(file, &mut set, &mut buffer, logger);
+    }
+
+    Ok(buffer)
+}
+
+fn collect_tpas(path: &Path,
+    recursive: bool,
+    extensions: &[String],
+    candidates: &mut Vec<PathBuf>) -> io::Result<()> {
+    // read_dir's order is up to the filesystem, so sort to keep the TPA
+    // list (and which duplicate wins) the same from run to run.
+    let mut entries = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if recursive && entry.file_type()?.is_dir() {
+            collect_tpas(&path, recursive, extensions, candidates)?;
+            continue;
+        }
+
+        if is_assembly(&path, extensions) {
+            candidates.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+// the position of the first priority entry naming this file, either by
+// file name (ignoring case) or by full path, or priority.len() if none does.
+fn tpa_priority(file: &Path, priority: &[String]) -> usize {
+    let name = file.file_name().map(|n| n.to_string_lossy().to_lowercase());
+    priority.iter()
+        .position(|p| Path::new(p) == file || Some(p.to_lowercase()) == name)
+        .unwrap_or(priority.len())
+}
+
+// a published app is the one assembly with a runtimeconfig.json next to
+// it. if there isn't exactly one, we don't guess.
+fn find_app_assembly(dir: &Path) -> Option<PathBuf> {
+    let mut found = None;
+    for entry in fs::read_dir(dir).ok()?.filter_map(|e| e.ok()) {
+        let name = entry.file_name();
+        if let Some(app) = name.to_str().and_then(|n| n.strip_suffix(".runtimeconfig.json")) {
+            if found.is_some() {
+                return None;
             }
+
+            found = Some(dir.join(format!("{}.dll", app)));
         }
     }
 
-    Ok(buffer.join(":"))
-}
\ No newline at end of file
+    found.filter(|p| p.is_file())
+}
+
+// breadth first, so the shallowest match wins, and in name order within
+// a level so the same one is found every time.
+fn find_file_below(dir: &Path, name: &str, depth: usize) -> Option<PathBuf> {
+    let mut level = vec![dir.to_path_buf()];
+    for _ in 0..depth {
+        let mut next = vec![];
+        for dir in &level {
+            let mut subdirs : Vec<PathBuf> = match fs::read_dir(dir) {
+                Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_dir()).collect(),
+                Err(_) => continue
+            };
+
+            subdirs.sort();
+            next.extend(subdirs);
+        }
+
+        if let Some(found) = next.iter().map(|d| d.join(name)).find(|p| p.is_file()) {
+            return Some(found);
+        }
+
+        level = next;
+    }
+
+    None
+}
+
+fn is_assembly(path: &Path, extensions: &[String]) -> bool {
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name.to_lowercase(),
+        None => return false
+    };
+
+    // "foo.ni.dll" has the extension "ni.dll", but "foo.dll" doesn't.
+    extensions.iter().any(|ext| {
+        name.len() > ext.len() + 1
+            && name.ends_with(ext.as_str())
+            && name[..name.len() - ext.len()].ends_with('.')
+    })
+}
+
+fn add_tpa(path: &Path,
+    set: &mut HashSet<String>,
+    buffer: &mut Vec<PathBuf>,
+    logger: Option<&logging::Logger>) {
+    let path = &resolve_links(path);
+    // the runtime binds by simple name, so two files with the same
+    // name in different directories are duplicates. the first one
+    // found wins.
+    let name = match path.file_stem() {
+        Some(s) => s.to_string_lossy().to_lowercase(),
+        None => return
+    };
+
+    if set.insert(name) {
+        logging::log(logger, || format!("added TPA {}", path.display()));
+        buffer.push(path.to_path_buf());
+    } else {
+        logging::log(logger, || format!("skipped duplicate TPA {}", path.display()));
+    }
+}
+
+#[cfg(unix)]
+fn load_library(path: &Path, global_symbols: bool, mode: loader::LoadMode) -> io::Result<loader::DynamicLibrary> {
+    if global_symbols {
+        loader::DynamicLibrary::load_with_flags(path, mode.dlopen_flags() | libc::RTLD_GLOBAL)
+    } else {
+        loader::DynamicLibrary::load_with_mode(path, mode)
+    }
+}
+
+#[cfg(not(unix))]
+fn load_library(path: &Path, _global_symbols: bool, mode: loader::LoadMode) -> io::Result<loader::DynamicLibrary> {
+    loader::DynamicLibrary::load_with_mode(path, mode)
+}
+
+// the runtime takes paths as byte strings. any path is one on unix, but on
+// windows the runtime expects them to be UTF-8.
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Result<Vec<u8>, ClrError> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(path.as_os_str().as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Result<Vec<u8>, ClrError> {
+    match path.to_str() {
+        Some(s) => Ok(s.as_bytes().to_vec()),
+        None => Err(ClrError::NonUtf8Path(path.to_path_buf()))
+    }
+}
+
+fn join_path_list(paths: &[PathBuf]) -> Result<Vec<u8>, ClrError> {
+    let mut joined = vec![];
+    for path in paths {
+        if !joined.is_empty() {
+            joined.push(PATH_LIST_SEP as u8);
+        }
+
+        joined.extend(path_bytes(path)?);
+    }
+
+    Ok(joined)
+}
+
+fn join_path_string(paths: &[PathBuf]) -> Result<String, ClrError> {
+    let mut joined = String::new();
+    for path in paths {
+        if let Some(s) = path.to_str() {
+            if !joined.is_empty() {
+                joined.push(PATH_LIST_SEP);
+            }
+
+            joined.push_str(s);
+        } else {
+            return Err(ClrError::NonUtf8Path(path.clone()));
+        }
+    }
+
+    Ok(joined)
+}
+
+// names what held the NUL, which NulError's own message doesn't.
+fn c_string<T: Into<Vec<u8>>, F: FnOnce() -> String>(value: T, what: F) -> Result<CString, ClrError> {
+    CString::new(value).map_err(|_| Error::new(ErrorKind::InvalidInput,
+        format!("{} contains an interior NUL byte", what())).into())
+}
+
+/// An argv laid out like C's main gets it, owning the strings its pointers
+/// point into so they can't be freed while it's in use. The array is
+/// NULL-terminated, so it's never empty and the pointer handed out is
+/// valid even when argc is 0.
+struct Argv {
+    // never read, but the pointers are into these. moving the Vec doesn't
+    // move the strings' own buffers, so Argv itself can move freely.
+    _strings: Vec<CString>,
+    pointers: Vec<*const c_char>,
+}
+
+impl Argv {
+    fn new(args: &[&str]) -> Result<Argv, ClrError> {
+        let strings = args.iter()
+            .enumerate()
+            .map(|(i, arg)| c_string(*arg, || format!("argument {}", i)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let pointers = strings.iter()
+            .map(|arg| arg.as_ptr())
+            .chain(Some(std::ptr::null()))
+            .collect();
+        Ok(Argv {
+            _strings: strings,
+            pointers
+        })
+    }
+
+    fn argc(&self) -> c_int {
+        (self.pointers.len() - 1) as c_int
+    }
+
+    /// Only valid for as long as this `Argv` is alive.
+    fn as_mut_ptr(&mut self) -> *mut *const c_char {
+        self.pointers.as_mut_ptr()
+    }
+}
+
+/// Sets environment variables for as long as it lives, then puts back
+/// whatever was there before.
+struct EnvGuard {
+    saved: Vec<(String, Option<OsString>)>
+}
+
+impl EnvGuard {
+    fn set<V: AsRef<OsStr>>(vars: &[(&str, V)]) -> EnvGuard {
+        let mut saved = vec![];
+        for &(key, ref value) in vars {
+            saved.push((key.to_string(), env::var_os(key)));
+            env::set_var(key, value);
+        }
+
+        EnvGuard {
+            saved
+        }
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        for (key, value) in self.saved.drain(..).rev() {
+            match value {
+                Some(v) => env::set_var(&key, v),
+                None => env::remove_var(&key)
+            }
+        }
+    }
+}
+
+/// Changes the current directory for as long as it lives.
+struct CwdGuard {
+    saved: PathBuf
+}
+
+impl CwdGuard {
+    fn set(dir: &Path) -> io::Result<CwdGuard> {
+        let saved = env::current_dir()?;
+        env::set_current_dir(dir)?;
+        Ok(CwdGuard {
+            saved
+        })
+    }
+}
+
+impl Drop for CwdGuard {
+    fn drop(&mut self) {
+        let _ = env::set_current_dir(&self.saved);
+    }
+}
+
+/// Looks up `name` in `lib` as a value of type `T`, which is meant to be
+/// the symbol's function pointer type.
+///
+/// # Safety
+/// `T` must be a pointer-sized type matching what the symbol really is.
+unsafe fn resolve<T: Copy>(lib: &loader::DynamicLibrary,
+    name: &str,
+    logger: Option<&logging::Logger>) -> Result<T, ClrError> {
+    assert!(mem::size_of::<T>() == mem::size_of::<*mut c_void>(),
+        "symbols can only be resolved as pointer-sized types");
+    let symbol = lib.resolve_symbol(name).map_err(|_| ClrError::SymbolNotFound(name.to_string()))?;
+    logging::log(logger, || format!("resolved {} at {:p}", name, symbol));
+    Ok(mem::transmute_copy::<*mut c_void, T>(&symbol))
+}
+
+// the runtime resolves relative assembly paths against its own idea of
+// the working directory, so it only ever gets absolute ones. verbatim
+// (\\?\) paths from canonicalize aren't safe to hand it on Windows.
+fn absolute_assembly_path(path: &Path) -> Result<PathBuf, ClrError> {
+    if !path.is_file() {
+        return Err(Error::new(ErrorKind::NotFound,
+            format!("assembly {} does not exist", path.display())).into());
+    }
+
+    if cfg!(windows) {
+        Ok(std::path::absolute(path)?)
+    } else {
+        Ok(fs::canonicalize(path)?)
+    }
+}
+
+// resolves symlinks, so that a link and its target count as the same file
+// and the runtime is given the real location. not on windows, where
+// canonical paths have a \\?\ prefix the runtime doesn't expect.
+fn resolve_links(path: &Path) -> PathBuf {
+    if cfg!(windows) {
+        path.to_path_buf()
+    } else {
+        canonicalize_or_raw(path)
+    }
+}
+
+// why a runtime couldn't be started because RUNTIME_ACTIVE was already set.
+fn runtime_unavailable() -> ClrError {
+    if RUNTIME_SHUT_DOWN.load(Ordering::SeqCst) {
+        Error::new(ErrorKind::Unsupported, error::SHUT_DOWN).into()
+    } else {
+        Error::new(ErrorKind::AlreadyExists, error::ALREADY_INITIALIZED).into()
+    }
+}
+
+fn canonicalize_or_raw(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
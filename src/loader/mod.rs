@@ -1,5 +1,30 @@
 #[cfg(unix)]
 mod unix;
 
+#[cfg(windows)]
+mod windows;
+
+#[cfg(not(any(unix, windows)))]
+mod unsupported;
+
 #[cfg(unix)]
 pub use self::unix::DynamicLibrary;
+
+#[cfg(windows)]
+pub use self::windows::DynamicLibrary;
+
+#[cfg(not(any(unix, windows)))]
+pub use self::unsupported::DynamicLibrary;
+
+/// When the symbols of a loaded library get bound.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoadMode {
+    /// Resolve every symbol up front (`RTLD_NOW`). Unresolvable symbols
+    /// make the load itself fail, even ones that would never be called.
+    #[default]
+    Now,
+    /// Resolve symbols on first use (`RTLD_LAZY`). Loading succeeds on
+    /// systems missing some of the library's dependencies, at the cost of
+    /// a crash later if one of them is actually called.
+    Lazy,
+}
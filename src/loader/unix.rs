@@ -1,55 +1,94 @@
 //! Dynamic library loader for Unixes.
 use libc;
 use std::path::Path;
-use std::ffi::CString;
-use std::io::{self, Error, ErrorKind};
+use std::ffi::{CStr, CString};
+use std::io::{self, Error};
+use std::os::unix::ffi::OsStrExt;
+use logging::{self, Logger};
+use super::LoadMode;
 
 pub struct DynamicLibrary {
-    handle: *mut libc::c_void
+    handle: *mut libc::c_void,
+    logger: Option<Logger>
 }
 
 impl DynamicLibrary {
+    #[allow(dead_code)]
     pub fn load(path: &Path) -> io::Result<DynamicLibrary> {
-        let cstr = if let Some(s) = path.to_str() {
-            try!(CString::new(s))
-        } else {
-            return Err(Error::new(ErrorKind::InvalidInput, "non-UTF8 path"));
-        };
+        DynamicLibrary::load_with_mode(path, LoadMode::Now)
+    }
+
+    pub fn load_with_mode(path: &Path, mode: LoadMode) -> io::Result<DynamicLibrary> {
+        DynamicLibrary::load_with_flags(path, mode.dlopen_flags())
+    }
 
-        let handle = unsafe { libc::dlopen(cstr.as_ptr(), 0x2 /* RTLD_NOW */) };
+    /// Loads the library, passing `flags` (e.g. `RTLD_NOW | RTLD_GLOBAL`)
+    /// straight through to dlopen.
+    pub fn load_with_flags(path: &Path, flags: libc::c_int) -> io::Result<DynamicLibrary> {
+        let cstr = CString::new(path.as_os_str().as_bytes())?;
+
+        let handle = unsafe { libc::dlopen(cstr.as_ptr(), flags) };
         if handle.is_null() {
-            unsafe {
-                let err = libc::dlerror();
-                let string = CString::from_raw(err);
-                let actual_string = string.into_string().expect("the OS gave us a non-UTF8 string");
-                Err(Error::new(ErrorKind::Other, actual_string))
-            }
+            Err(Error::other(unsafe { dlerror_message() }))
         } else {
             Ok(DynamicLibrary {
-                handle: handle
+                handle,
+                logger: None
             })
         }
     }
 
+    /// Sets where a failure to unload the library gets reported.
+    pub fn set_logger(&mut self, logger: Option<Logger>) {
+        self.logger = logger;
+    }
+
     pub unsafe fn resolve_symbol<T: Into<Vec<u8>>>(&self, name: T) -> io::Result<*mut libc::c_void> {
-        let cstr = try!(CString::new(name));
+        let cstr = CString::new(name)?;
 
+        // a null return from dlsym doesn't necessarily mean failure, and
+        // dlerror may still hold a message from an earlier call. so clear
+        // it first, and only treat a fresh message as an error.
+        libc::dlerror();
         let result = libc::dlsym(self.handle, cstr.as_ptr());
-        if result.is_null() {
-            let err = libc::dlerror();
-            let string = CString::from_raw(err);
-            let actual_string = string.into_string().expect("the OS gave us a non-UTF8 string");
-            Err(Error::new(ErrorKind::Other, actual_string))
+        let err = libc::dlerror();
+        if !err.is_null() {
+            // the message lives in a buffer owned by libc, so copy it out
+            // rather than taking ownership of it.
+            Err(Error::other(CStr::from_ptr(err).to_string_lossy().into_owned()))
         } else {
             Ok(result)
         }
     }
 }
 
+impl LoadMode {
+    pub fn dlopen_flags(self) -> libc::c_int {
+        match self {
+            LoadMode::Now => libc::RTLD_NOW,
+            LoadMode::Lazy => libc::RTLD_LAZY
+        }
+    }
+}
+
 impl Drop for DynamicLibrary {
     fn drop(&mut self) {
-        unsafe {
-            libc::dlclose(self.handle);
+        let result = unsafe { libc::dlclose(self.handle) };
+        if result != 0 {
+            let message = unsafe { dlerror_message() };
+            debug_assert!(false, "dlclose failed: {}", message);
+            logging::log(self.logger.as_ref(), || format!("dlclose failed: {}", message));
         }
     }
-}
\ No newline at end of file
+}
+
+/// Copies out the message from the last failed dl* call, if any. The
+/// string belongs to libc and must never be freed by us.
+unsafe fn dlerror_message() -> String {
+    let err = libc::dlerror();
+    if err.is_null() {
+        "unknown error".to_string()
+    } else {
+        CStr::from_ptr(err).to_string_lossy().into_owned()
+    }
+}
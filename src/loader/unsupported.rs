@@ -0,0 +1,32 @@
+//! Stand-in loader for targets that are neither Unix nor Windows, so the
+//! crate still compiles there and fails when it's used instead.
+use std::os::raw::c_void;
+use std::path::Path;
+use std::io::{self, Error, ErrorKind};
+use logging::Logger;
+use super::LoadMode;
+
+pub struct DynamicLibrary {
+    _private: ()
+}
+
+impl DynamicLibrary {
+    #[allow(dead_code)]
+    pub fn load(_path: &Path) -> io::Result<DynamicLibrary> {
+        Err(unsupported())
+    }
+
+    pub fn load_with_mode(_path: &Path, _mode: LoadMode) -> io::Result<DynamicLibrary> {
+        Err(unsupported())
+    }
+
+    pub fn set_logger(&mut self, _logger: Option<Logger>) {}
+
+    pub unsafe fn resolve_symbol<T: Into<Vec<u8>>>(&self, _name: T) -> io::Result<*mut c_void> {
+        Err(unsupported())
+    }
+}
+
+fn unsupported() -> Error {
+    Error::new(ErrorKind::Unsupported, "dynamic loading is not supported on this target")
+}
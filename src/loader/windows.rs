@@ -0,0 +1,76 @@
+//! Dynamic library loader for Windows.
+use libc;
+use std::path::Path;
+use std::ffi::CString;
+use std::os::windows::ffi::OsStrExt;
+use std::io::{self, Error};
+use logging::{self, Logger};
+use super::LoadMode;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn LoadLibraryW(file_name: *const u16) -> *mut libc::c_void;
+    fn GetProcAddress(module: *mut libc::c_void, proc_name: *const libc::c_char) -> *mut libc::c_void;
+    fn FreeLibrary(module: *mut libc::c_void) -> libc::c_int;
+    fn GetLastError() -> u32;
+}
+
+pub struct DynamicLibrary {
+    handle: *mut libc::c_void,
+    logger: Option<Logger>
+}
+
+impl DynamicLibrary {
+    pub fn load(path: &Path) -> io::Result<DynamicLibrary> {
+        let wide: Vec<u16> = path.as_os_str()
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+
+        let handle = unsafe { LoadLibraryW(wide.as_ptr()) };
+        if handle.is_null() {
+            Err(last_error())
+        } else {
+            Ok(DynamicLibrary {
+                handle,
+                logger: None
+            })
+        }
+    }
+
+    /// Windows always binds imports when the library is loaded, so the
+    /// mode makes no difference here.
+    pub fn load_with_mode(path: &Path, _mode: LoadMode) -> io::Result<DynamicLibrary> {
+        DynamicLibrary::load(path)
+    }
+
+    /// Sets where a failure to unload the library gets reported.
+    pub fn set_logger(&mut self, logger: Option<Logger>) {
+        self.logger = logger;
+    }
+
+    pub unsafe fn resolve_symbol<T: Into<Vec<u8>>>(&self, name: T) -> io::Result<*mut libc::c_void> {
+        let cstr = CString::new(name)?;
+
+        let result = GetProcAddress(self.handle, cstr.as_ptr());
+        if result.is_null() {
+            Err(last_error())
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+impl Drop for DynamicLibrary {
+    fn drop(&mut self) {
+        if unsafe { FreeLibrary(self.handle) } == 0 {
+            let err = last_error();
+            logging::log(self.logger.as_ref(), || format!("FreeLibrary failed: {}", err));
+        }
+    }
+}
+
+fn last_error() -> Error {
+    let code = unsafe { GetLastError() };
+    Error::from_raw_os_error(code as i32)
+}
@@ -0,0 +1,32 @@
+//! The diagnostic logging hook set by `ClrHostBuilder::with_logger`.
+use std::fmt;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Logger(Arc<dyn Fn(&str) + Send + Sync>);
+
+// nothing the crate relies on lives inside the callback, so a panic
+// partway through a call can't leave the crate's own state inconsistent.
+impl UnwindSafe for Logger {}
+impl RefUnwindSafe for Logger {}
+
+impl Logger {
+    pub fn new(f: Box<dyn Fn(&str) + Send + Sync>) -> Logger {
+        Logger(Arc::from(f))
+    }
+}
+
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Logger(..)")
+    }
+}
+
+/// Hands the message built by `message` to `logger`. Nothing is formatted
+/// unless a logger is actually present.
+pub fn log<F: FnOnce() -> String>(logger: Option<&Logger>, message: F) {
+    if let Some(logger) = logger {
+        (logger.0)(&message());
+    }
+}
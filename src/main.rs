@@ -1,7 +1,7 @@
 extern crate coreclr;
-extern crate libc;
 
 use std::ffi::CString;
+use std::os::raw::c_char;
 
 fn main() {
     let ret = main_impl();
@@ -33,18 +33,20 @@ fn main_impl() -> usize {
     };
 
     // if we don't do this, rustc doesn't emit this symbol D:
-    rust_pinvoke_target(std::ptr::null_mut());
+    unsafe { rust_pinvoke_target(std::ptr::null_mut()) };
 
     exit_code
 }
 
+/// # Safety
+/// `string` must be null or a string allocated by `CString::into_raw`.
 #[no_mangle]
-pub extern "C" fn rust_pinvoke_target(string: *mut libc::c_char) {
+pub unsafe extern "C" fn rust_pinvoke_target(string: *mut c_char) {
     if string.is_null() {
         return;
     }
 
-    let s = unsafe { CString::from_raw(string) };
+    let s = CString::from_raw(string);
     let string = match s.into_string() {
         Ok(s) => s,
         Err(_) => "non-UTF8 string".to_string()
@@ -0,0 +1,317 @@
+//! Running CoreCLR in a child process, for when one process needs more than
+//! one runtime. See `ClrHostBuilder::build_out_of_process`.
+//!
+//! The child is the current executable again, told where its end of two
+//! pipes is through `REMOTE_FDS_VAR`. Every message is a little-endian u32
+//! byte count followed by that many bytes, which hold a u32 field count and
+//! then each field as a u32 length and its bytes. The first field of a
+//! request names it; the first field of a reply is `ok` or `err`.
+//!
+//! Requests, and what an `ok` reply carries:
+//!
+//! * `init`: starts the runtime, see `init_message`. Nothing.
+//! * `execute`, assembly path, args...: the exit code, in decimal.
+//!
+//! The child shuts the runtime down and exits once its request pipe closes.
+use libc;
+use std::env;
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+use loader::LoadMode;
+use {ClrError, ClrHost, ClrHostBuilder, PreparedHost};
+
+const REMOTE_FDS_VAR: &str = "CORECLR_RS_REMOTE_FDS";
+
+/// A runtime started by `ClrHostBuilder::build_out_of_process`, living in a
+/// child process. Dropping it shuts the runtime down and waits for the
+/// child to exit.
+pub struct RemoteClrHost {
+    child: Child,
+    requests: Option<File>,
+    replies: File,
+}
+
+impl RemoteClrHost {
+    pub(crate) fn spawn(builder: &ClrHostBuilder, prepared: &PreparedHost) -> Result<RemoteClrHost, ClrError> {
+        // a child whose main never called serve_remote_host would
+        // otherwise go on to spawn children of its own.
+        if env::var_os(REMOTE_FDS_VAR).is_some() {
+            return Err(Error::other(
+                "this process was started by build_out_of_process, but main didn't call serve_remote_host first").into());
+        }
+
+        let (request_read, request_write) = pipe()?;
+        let (reply_read, reply_write) = pipe()?;
+        let child_fds = [request_read.as_raw_fd(), reply_write.as_raw_fd()];
+        let mut command = Command::new(env::current_exe()?);
+        command.env(REMOTE_FDS_VAR, format!("{},{}", child_fds[0], child_fds[1]));
+        command.envs(builder.startup_env_vars());
+        unsafe {
+            command.pre_exec(move || {
+                for &fd in &child_fds {
+                    set_cloexec(fd, false)?;
+                }
+
+                Ok(())
+            });
+        }
+
+        let child = command.spawn()?;
+        drop(request_read);
+        drop(reply_write);
+        let mut host = RemoteClrHost {
+            child,
+            requests: Some(request_write),
+            replies: reply_read
+        };
+
+        host.call(&init_message(builder, prepared))?;
+        Ok(host)
+    }
+
+    /// Runs the assembly's entry point in the child. Otherwise the same as
+    /// `ClrHost::execute_assembly`, except that errors from the child only
+    /// keep their message.
+    pub fn execute_assembly<T: Into<PathBuf>>(&mut self, args: &[&str], assembly_path: T) -> Result<usize, ClrError> {
+        let path = assembly_path.into();
+        let mut request = vec![&b"execute"[..], path.as_os_str().as_bytes()];
+        request.extend(args.iter().map(|a| a.as_bytes()));
+        let reply = self.call(&request)?;
+        match reply.first().and_then(|code| String::from_utf8_lossy(code).parse().ok()) {
+            Some(code) => Ok(code),
+            None => Err(Error::new(ErrorKind::InvalidData, "malformed reply from the remote host").into())
+        }
+    }
+
+    /// The id of the child process.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    fn call(&mut self, request: &[&[u8]]) -> Result<Vec<Vec<u8>>, ClrError> {
+        let requests = self.requests.as_mut().expect("request pipe is only closed on drop");
+        write_message(requests, request)?;
+        let mut reply = read_message(&mut self.replies).map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => Error::new(ErrorKind::BrokenPipe, "the remote host exited unexpectedly"),
+            _ => err
+        })?;
+
+        if reply.first().map(|f| &f[..]) == Some(&b"ok"[..]) {
+            Ok(reply.split_off(1))
+        } else {
+            let message = reply.get(1).map(|m| String::from_utf8_lossy(m).into_owned()).unwrap_or_default();
+            Err(Error::other(format!("remote host: {}", message)).into())
+        }
+    }
+}
+
+impl Drop for RemoteClrHost {
+    fn drop(&mut self) {
+        self.requests = None;
+        let _ = self.child.wait();
+    }
+}
+
+/// Serves a `RemoteClrHost` if this process was started as one by
+/// `ClrHostBuilder::build_out_of_process`, exiting once it's dropped.
+/// Otherwise returns straight away. Call it first thing in `main` in any
+/// program that uses `build_out_of_process`.
+pub fn serve_remote_host() {
+    let fds = match env::var(REMOTE_FDS_VAR) {
+        Ok(fds) => fds,
+        Err(_) => return
+    };
+
+    env::remove_var(REMOTE_FDS_VAR);
+    let code = match serve(&fds) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("coreclr remote host: {}", err);
+            1
+        }
+    };
+
+    process::exit(code);
+}
+
+fn serve(fds: &str) -> io::Result<()> {
+    let fds : Vec<RawFd> = fds.split(',').filter_map(|fd| fd.parse().ok()).collect();
+    if fds.len() != 2 {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("bad {}", REMOTE_FDS_VAR)));
+    }
+
+    let (mut requests, mut replies) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    let host = {
+        let init = read_message(&mut requests)?;
+        match start(&init) {
+            Ok(host) => {
+                write_message(&mut replies, &[b"ok"])?;
+                host
+            }
+            Err(err) => return write_message(&mut replies, &[b"err", err.to_string().as_bytes()])
+        }
+    };
+
+    loop {
+        let request = match read_message(&mut requests) {
+            Ok(request) => request,
+            Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err)
+        };
+
+        let result = match request.first().map(|f| &f[..]) {
+            Some(b"execute") if request.len() >= 2 => execute(&host, &request[1..]),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "unknown request").into())
+        };
+
+        match result {
+            Ok(code) => write_message(&mut replies, &[b"ok", code.to_string().as_bytes()])?,
+            Err(err) => write_message(&mut replies, &[b"err", err.to_string().as_bytes()])?
+        }
+    }
+}
+
+fn execute(host: &ClrHost, request: &[Vec<u8>]) -> Result<usize, ClrError> {
+    let path = Path::new(OsStr::from_bytes(&request[0]));
+    let mut args = vec![];
+    for arg in &request[1..] {
+        args.push(String::from_utf8(arg.clone())
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "argument is not valid UTF-8"))?);
+    }
+
+    let args : Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    host.execute_assembly(&args, path)
+}
+
+// init, coreclr library, assembly, appdomain name, global symbols, load
+// mode, working directory, preloads... and an empty field to end them,
+// then property keys and values in turn. Otherwise, empty fields mean
+// "not set". knobs read from the environment are set on the child instead.
+fn init_message<'a>(builder: &'a ClrHostBuilder, prepared: &'a PreparedHost) -> Vec<&'a [u8]> {
+    let mut message : Vec<&[u8]> = vec![
+        b"init",
+        prepared.coreclr_path.as_os_str().as_bytes(),
+        prepared.assembly.as_bytes(),
+        prepared.name.as_bytes(),
+        if builder.global_symbols { b"1" } else { b"" },
+        if builder.load_mode == LoadMode::Lazy { b"lazy" } else { b"now" },
+        builder.working_directory.as_ref().map(|d| d.as_os_str().as_bytes()).unwrap_or(b""),
+    ];
+
+    message.extend(builder.preload_libraries.iter().map(|p| p.as_os_str().as_bytes()));
+    message.push(b"");
+    for (key, value) in &prepared.properties {
+        message.push(key.as_bytes());
+        message.push(value.as_bytes());
+    }
+
+    message
+}
+
+fn start(init: &[Vec<u8>]) -> Result<ClrHost, ClrError> {
+    let malformed = || ClrError::from(Error::new(ErrorKind::InvalidData, "malformed init request"));
+    if init.len() < 8 || init[0] != b"init" {
+        return Err(malformed());
+    }
+
+    let path = |bytes: &[u8]| PathBuf::from(OsStr::from_bytes(bytes));
+    let mut builder = ClrHostBuilder::new();
+    builder.global_symbols = init[4] == b"1";
+    builder.load_mode = if init[5] == b"lazy" { LoadMode::Lazy } else { LoadMode::Now };
+    if !init[6].is_empty() {
+        builder.working_directory = Some(path(&init[6]));
+    }
+
+    let mut rest = init[7..].iter();
+    for preload in rest.by_ref() {
+        if preload.is_empty() {
+            break;
+        }
+
+        builder.preload_libraries.push(path(preload));
+    }
+
+    let mut properties = vec![];
+    while let Some(key) = rest.next() {
+        let value = rest.next().ok_or_else(malformed)?;
+        properties.push((CString::new(key.clone())?, CString::new(value.clone())?));
+    }
+
+    builder.start(PreparedHost {
+        coreclr_path: path(&init[1]),
+        assembly: CString::new(init[2].clone())?,
+        name: CString::new(init[3].clone())?,
+        properties,
+        // only create_delegate_from_path looks at these, and that isn't
+        // proxied.
+        assembly_load_paths: vec![]
+    })
+}
+
+fn write_message<W: Write>(writer: &mut W, fields: &[&[u8]]) -> io::Result<()> {
+    let mut body = vec![];
+    body.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+    for field in fields {
+        body.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        body.extend_from_slice(field);
+    }
+
+    writer.write_all(&(body.len() as u32).to_le_bytes())?;
+    writer.write_all(&body)?;
+    writer.flush()
+}
+
+fn read_message<R: Read>(reader: &mut R) -> io::Result<Vec<Vec<u8>>> {
+    let mut body = vec![0; read_u32(reader)? as usize];
+    reader.read_exact(&mut body)?;
+    let mut body = &body[..];
+    let count = read_u32(&mut body)?;
+    let mut fields = vec![];
+    for _ in 0..count {
+        let mut field = vec![0; read_u32(&mut body)? as usize];
+        body.read_exact(&mut field)?;
+        fields.push(field);
+    }
+
+    Ok(fields)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// both ends start out close-on-exec; the child's are made inheritable only
+// in the child, so no other process started meanwhile gets them.
+fn pipe() -> io::Result<(File, File)> {
+    unsafe {
+        let mut fds = [0; 2];
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let files = (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]));
+        set_cloexec(fds[0], true)?;
+        set_cloexec(fds[1], true)?;
+        Ok(files)
+    }
+}
+
+fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        let flags = if cloexec { flags | libc::FD_CLOEXEC } else { flags & !libc::FD_CLOEXEC };
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,247 @@
+//! Reading the parts of an app's `.runtimeconfig.json` that a host acts on,
+//! for `ClrHostBuilder::with_runtime_config`. The JSON parser here only
+//! goes as far as this file format needs.
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+pub struct RuntimeConfig {
+    /// `configProperties`, with values rendered the way the dotnet host
+    /// hands them to the runtime.
+    pub properties: Vec<(String, String)>,
+    /// The version of `Microsoft.NETCore.App` the app targets.
+    pub framework_version: Option<String>,
+}
+
+pub fn read(path: &Path) -> io::Result<RuntimeConfig> {
+    let text = fs::read_to_string(path)?;
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData,
+        format!("{} is not a valid runtimeconfig.json: {}", path.display(), message));
+    let text = text.trim_start_matches('\u{feff}');
+    let json = Parser { text, pos: 0 }.parse_document().map_err(&invalid)?;
+    let options = match json.get("runtimeOptions") {
+        Some(options) => options,
+        None => return Err(invalid("it has no runtimeOptions".to_string()))
+    };
+
+    let mut properties = vec![];
+    if let Some(Json::Object(members)) = options.get("configProperties") {
+        for (key, value) in members {
+            let value = match *value {
+                Json::Bool(b) => b.to_string(),
+                Json::Number(ref n) | Json::String(ref n) => n.clone(),
+                _ => return Err(invalid(format!("configProperties.{} is not a string, number or bool", key)))
+            };
+
+            properties.push((key.clone(), value));
+        }
+    }
+
+    // .NET 6 apps that use more than one framework list them all under
+    // "frameworks" instead.
+    let mut frameworks = vec![];
+    frameworks.extend(options.get("framework"));
+    if let Some(Json::Array(items)) = options.get("frameworks") {
+        frameworks.extend(items);
+    }
+
+    let framework_version = frameworks.iter()
+        .find(|f| f.get("name").and_then(Json::as_str) == Some("Microsoft.NETCore.App"))
+        .and_then(|f| f.get("version"))
+        .and_then(Json::as_str)
+        .map(|v| v.to_string());
+
+    Ok(RuntimeConfig {
+        properties,
+        framework_version
+    })
+}
+
+enum Json {
+    Null,
+    Bool(bool),
+    // kept as written, since that's what the runtime gets to parse.
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.iter().find(|m| m.0 == key).map(|m| &m.1),
+            _ => None
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse_document(mut self) -> Result<Json, String> {
+        let value = self.parse_value()?;
+        self.skip_whitespace();
+        if self.pos != self.text.len() {
+            return Err(self.error("trailing characters"));
+        }
+
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.parse_object(),
+            Some('[') => self.parse_array(),
+            Some('"') => self.parse_string().map(Json::String),
+            Some('t') => self.parse_literal("true", Json::Bool(true)),
+            Some('f') => self.parse_literal("false", Json::Bool(false)),
+            Some('n') => self.parse_literal("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let len = self.rest().find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c))).unwrap_or(self.rest().len());
+                let number = self.rest()[..len].to_string();
+                self.pos += len;
+                Ok(Json::Number(number))
+            }
+            _ => Err(self.error("expected a value"))
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut members = vec![];
+        loop {
+            self.skip_whitespace();
+            // the dotnet host tolerates trailing commas, so this does too.
+            if self.eat('}') {
+                return Ok(Json::Object(members));
+            }
+
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a property name"));
+            }
+
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            if !self.eat(':') {
+                return Err(self.error("expected ':'"));
+            }
+
+            members.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            if !self.eat(',') && self.peek() != Some('}') {
+                return Err(self.error("expected ',' or '}'"));
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut items = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(Json::Array(items));
+            }
+
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            if !self.eat(',') && self.peek() != Some(']') {
+                return Err(self.error("expected ',' or ']'"));
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut result = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(result);
+                }
+                '\\' => match chars.next().map(|e| e.1) {
+                    Some('"') => result.push('"'),
+                    Some('\\') => result.push('\\'),
+                    Some('/') => result.push('/'),
+                    Some('b') => result.push('\u{8}'),
+                    Some('f') => result.push('\u{c}'),
+                    Some('n') => result.push('\n'),
+                    Some('r') => result.push('\r'),
+                    Some('t') => result.push('\t'),
+                    Some('u') => {
+                        let hex : String = chars.by_ref().take(4).map(|e| e.1).collect();
+                        let code = u32::from_str_radix(&hex, 16).map_err(|_| self.error("bad \\u escape"))?;
+                        // surrogate pairs only show up in non-BMP text, which
+                        // no setting a host cares about contains.
+                        result.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    _ => return Err(self.error("bad escape"))
+                },
+                c => result.push(c)
+            }
+        }
+
+        Err(self.error("unterminated string"))
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Json) -> Result<Json, String> {
+        if self.rest().starts_with(literal) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("expected a value"))
+        }
+    }
+
+    // comments aren't JSON, but the dotnet host accepts them.
+    fn skip_whitespace(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                self.pos += trimmed.find("*/").map(|end| end + 2).unwrap_or(trimmed.len());
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        format!("{} on line {}", message, line)
+    }
+}
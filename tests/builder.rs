@@ -0,0 +1,134 @@
+//! Checks the builder's configuration, mostly what it would hand the
+//! runtime through `effective_properties`, against a stand-in coreclr
+//! directory. Nothing here loads or starts a runtime.
+extern crate coreclr;
+
+use coreclr::{ClrHostBuilder, PATH_LIST_SEP};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+
+#[test]
+fn with_properties_overrides_builtin_settings() {
+    let dir = fake_coreclr("with_properties");
+    let properties = ClrHostBuilder::new()
+        .with_coreclr_path(&dir)
+        .with_workstation_gc()
+        .with_properties(vec![
+            ("System.GC.Server".to_string(), "true".to_string()),
+            ("Test.Custom".to_string(), "value".to_string()),
+        ])
+        .effective_properties()
+        .unwrap();
+
+    assert_eq!(values(&properties, "System.GC.Server"), ["true"]);
+    assert_eq!(values(&properties, "Test.Custom"), ["value"]);
+}
+
+#[test]
+fn path_list_sep_matches_the_platform() {
+    let joined = env::join_paths(["a", "b"]).unwrap();
+    assert_eq!(joined.to_str(), Some(format!("a{}b", PATH_LIST_SEP).as_str()));
+}
+
+#[test]
+fn appdomain_compat_switch_defaults_to_latest_behavior() {
+    let dir = fake_coreclr("appdomain_compat_switch");
+    let properties = ClrHostBuilder::new()
+        .with_coreclr_path(&dir)
+        .effective_properties()
+        .unwrap();
+
+    assert_eq!(values(&properties, "AppDomainCompatSwitch"), ["UseLatestBehaviorWhenTFMNotSpecified"]);
+}
+
+#[test]
+fn clone_is_independent() {
+    let mut original = ClrHostBuilder::new();
+    original.with_coreclr_path("/original").with_assembly_probe_path("/original/probe");
+
+    let mut copy = original.clone();
+    copy.with_server_gc()
+        .with_nonconcurrent_gc()
+        .with_coreclr_path("/copy")
+        .with_assembly_probe_path("/copy/probe");
+
+    assert!(!original.server_gc());
+    assert!(original.concurrent_gc());
+    assert_eq!(original.coreclr_path(), Some(Path::new("/original")));
+    assert_eq!(original.assembly_probe_paths(), [PathBuf::from("/original/probe")]);
+    assert_eq!(copy.assembly_probe_paths().len(), 2);
+}
+
+#[test]
+fn app_context_base_directory_defaults_to_the_assembly_directory() {
+    let dir = fake_coreclr("app_context_base_directory");
+    let app_dir = dir.join("app");
+    fs::create_dir_all(&app_dir).unwrap();
+    fs::write(app_dir.join("App.dll"), b"").unwrap();
+    let properties = ClrHostBuilder::new()
+        .with_coreclr_path(&dir)
+        .with_assembly(app_dir.join("App.dll"))
+        .effective_properties()
+        .unwrap();
+
+    // the builder resolves symlinks in the assembly path, except on windows.
+    let app_dir = if cfg!(windows) { app_dir } else { fs::canonicalize(&app_dir).unwrap() };
+    let expected = format!("{}{}", app_dir.display(), MAIN_SEPARATOR);
+    assert_eq!(values(&properties, "APP_CONTEXT_BASE_DIRECTORY"), [expected.as_str()]);
+}
+
+#[test]
+fn native_search_directories_raw_replaces_probe_paths() {
+    let dir = fake_coreclr("native_search_directories_raw");
+    let raw = format!("/opt/a{}/opt/b", PATH_LIST_SEP);
+    let properties = ClrHostBuilder::new()
+        .with_coreclr_path(&dir)
+        .with_native_library_probe_path("/ignored")
+        .with_native_search_directories_raw(raw.as_str())
+        .effective_properties()
+        .unwrap();
+
+    let expected = format!("{}{}{}", dir.display(), PATH_LIST_SEP, raw);
+    assert_eq!(values(&properties, "NATIVE_DLL_SEARCH_DIRECTORIES"), [expected.as_str()]);
+}
+
+#[test]
+fn gc_heap_hard_limits_use_the_gc_property_names() {
+    let dir = fake_coreclr("gc_heap_hard_limit");
+    let properties = ClrHostBuilder::new()
+        .with_coreclr_path(&dir)
+        .with_gc_heap_hard_limit(256 * 1024 * 1024)
+        .with_gc_heap_hard_limit_percent(50)
+        .effective_properties()
+        .unwrap();
+
+    assert_eq!(values(&properties, "System.GC.HeapHardLimit"), ["0x10000000"]);
+    assert_eq!(values(&properties, "System.GC.HeapHardLimitPercent"), ["0x32"]);
+}
+
+// just enough of a runtime directory for the builder's checks: a coreclr
+// library to find, and an assembly for the TPA list.
+fn fake_coreclr(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("builder").join(name);
+    fs::create_dir_all(&dir).unwrap();
+    let library = if cfg!(target_os = "macos") {
+        "libcoreclr.dylib"
+    } else if cfg!(target_os = "linux") {
+        "libcoreclr.so"
+    } else {
+        "coreclr.dll"
+    };
+
+    fs::write(dir.join(library), b"").unwrap();
+    fs::write(dir.join("System.Private.CoreLib.dll"), b"").unwrap();
+    dir
+}
+
+// every value given for `key`, so a duplicate shows up as one.
+fn values<'a>(properties: &'a [(String, String)], key: &str) -> Vec<&'a str> {
+    properties.iter()
+        .filter(|p| p.0 == key)
+        .map(|p| p.1.as_str())
+        .collect()
+}
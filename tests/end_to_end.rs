@@ -0,0 +1,146 @@
+//! Runs a real assembly through a real runtime. Skipped unless CORECLR_PATH
+//! names the directory of a .NET 5 or later runtime (for example
+//! .../shared/Microsoft.NETCore.App/8.0.0) and the dotnet SDK is on the
+//! PATH to build tests/hello with.
+//!
+//! CoreCLR can only be started once per process, so everything that needs
+//! a host happens in a single test.
+extern crate coreclr;
+
+use coreclr::ClrHostBuilder;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+// the same layout as InArgs and OutResult in hello.cs.
+#[repr(C)]
+struct InArgs {
+    iterations: i32,
+    scale: f64,
+}
+
+#[repr(C)]
+struct OutResult {
+    total: f64,
+    count: i32,
+}
+
+// what hello.cs's Process reported through progress.
+static PROGRESS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+extern "C" fn progress(percent: i32) {
+    // a panic can't unwind into managed code.
+    let _ = panic::catch_unwind(|| PROGRESS.lock().unwrap().push(percent));
+}
+
+#[test]
+fn execute_and_create_delegate() {
+    let coreclr_path = match env::var_os("CORECLR_PATH") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            eprintln!("CORECLR_PATH is not set; skipping");
+            return;
+        }
+    };
+
+    let assembly = build_hello(&coreclr_path);
+    let log = Arc::new(Mutex::new(vec![]));
+    let log_writer = log.clone();
+    let mut host = ClrHostBuilder::new()
+        .with_coreclr_path(&coreclr_path)
+        .with_assembly(&assembly)
+        .with_assembly_directory_on_probe_path()
+        .with_logger(Box::new(move |line| log_writer.lock().unwrap().push(line.to_string())))
+        .build()
+        .expect("failed to start the runtime");
+
+    let add = unsafe {
+        host.create_delegate_fn::<extern "C" fn(i32, i32) -> i32>("hello", "Hello.Program", "Add")
+            .expect("failed to create a delegate for Hello.Program::Add")
+    };
+    assert_eq!(add(2, 3), 5);
+
+    let run = unsafe {
+        host.create_delegate_fn::<extern "C" fn(*const InArgs, *mut OutResult) -> i32>("hello", "Hello.Program", "Run")
+            .expect("failed to create a delegate for Hello.Program::Run")
+    };
+    let mut out = OutResult { total: 0.0, count: 0 };
+    assert_eq!(run(&InArgs { iterations: 10, scale: 0.5 }, &mut out), 0);
+    assert_eq!(out.total, 5.0);
+    assert_eq!(out.count, 10);
+
+    let process = unsafe {
+        host.create_delegate_fn::<extern "C" fn(extern "C" fn(i32))>("hello", "Hello.Program", "Process")
+            .expect("failed to create a delegate for Hello.Program::Process")
+    };
+    process(progress);
+    assert_eq!(*PROGRESS.lock().unwrap(), [0, 50, 100]);
+
+    // invoke_void checks signatures through the helper, which is only
+    // sure to be there when it's built in.
+    if cfg!(feature = "embedded-helper") {
+        host.invoke_void("hello", "Hello.Program", "Tick").expect("failed to invoke Hello.Program::Tick");
+        let ticks = unsafe {
+            host.create_delegate_fn::<extern "C" fn() -> i32>("hello", "Hello.Program", "Ticks")
+                .expect("failed to create a delegate for Hello.Program::Ticks")
+        };
+        assert_eq!(ticks(), 1);
+
+        let err = host.invoke_void("hello", "Hello.Program", "Add")
+            .expect_err("invoked Hello.Program::Add, which takes arguments and returns int");
+        assert_eq!(err.kind(), ErrorKind::NotFound, "{}", err);
+    }
+
+    let err = unsafe { host.create_delegate_from_path(&assembly, "Hello.Program", "Missing") }
+        .expect_err("resolved Hello.Program::Missing, which doesn't exist");
+    assert_eq!(err.kind(), ErrorKind::NotFound, "{}", err);
+    for part in &["Hello.Program::Missing", "assembly hello", assembly.to_str().unwrap()] {
+        assert!(err.to_string().contains(part), "{}", err);
+    }
+
+    // Main writes its first argument back out to the file named by the
+    // second.
+    let echo = assembly.with_file_name("echo.txt");
+    let argument = "café-naïve-日本.txt";
+    let _ = fs::remove_file(&echo);
+    let exit_code = host.execute_assembly(&[argument, echo.to_str().unwrap()], &assembly)
+        .expect("failed to execute hello.dll");
+    assert_eq!(exit_code, 0);
+    assert_eq!(fs::read_to_string(&echo).unwrap(), argument);
+
+    // shutdown drops the host on its way out, which mustn't shut the
+    // runtime down a second time.
+    host.shutdown().expect("failed to shut down the runtime");
+    let shutdowns = log.lock().unwrap().iter().filter(|line| *line == "shutting down the runtime").count();
+    assert_eq!(shutdowns, 1);
+}
+
+// builds outside the source tree, for the framework version CORECLR_PATH
+// is named after.
+fn build_hello(coreclr_path: &Path) -> PathBuf {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("hello");
+    let work = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hello");
+    fs::create_dir_all(&work).unwrap();
+    for file in &["hello.csproj", "hello.cs"] {
+        fs::copy(source.join(file), work.join(file)).unwrap();
+    }
+
+    let mut command = Command::new("dotnet");
+    command.arg("build").arg(work.join("hello.csproj"))
+        .args(["-c", "Release", "-o"]).arg(work.join("out"));
+    let major = coreclr_path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split('.').next())
+        .and_then(|major| major.parse::<u32>().ok());
+    if let Some(major) = major {
+        command.arg(format!("-p:TargetFramework=net{}.0", major));
+    }
+
+    let status = command.status().expect("CORECLR_PATH is set, but dotnet could not be run to build tests/hello");
+    assert!(status.success(), "building tests/hello failed");
+    work.join("out").join("hello.dll")
+}
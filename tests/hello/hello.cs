@@ -0,0 +1,64 @@
+using System;
+using System.IO;
+using System.Runtime.InteropServices;
+
+namespace Hello
+{
+    public static class Program
+    {
+        // With two arguments, writes the first to the file named by the
+        // second, so the test can see what arrived.
+        public static int Main(string[] args)
+        {
+            Console.WriteLine("hello from managed code");
+            if (args.Length == 2)
+            {
+                File.WriteAllText(args[1], args[0]);
+            }
+
+            return 0;
+        }
+
+        private static int ticks;
+
+        [UnmanagedCallersOnly]
+        public static int Add(int a, int b) => a + b;
+
+        [UnmanagedCallersOnly]
+        public static void Tick() => ticks++;
+
+        [UnmanagedCallersOnly]
+        public static int Ticks() => ticks;
+
+        [UnmanagedCallersOnly]
+        public static unsafe int Run(InArgs* input, OutResult* output)
+        {
+            output->Total = input->Iterations * input->Scale;
+            output->Count = input->Iterations;
+            return 0;
+        }
+
+        [UnmanagedCallersOnly]
+        public static unsafe void Process(delegate* unmanaged<int, void> progress)
+        {
+            for (int percent = 0; percent <= 100; percent += 50)
+            {
+                progress(percent);
+            }
+        }
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    public struct InArgs
+    {
+        public int Iterations;
+        public double Scale;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    public struct OutResult
+    {
+        public double Total;
+        public int Count;
+    }
+}
@@ -0,0 +1,65 @@
+//! Loads a system library in place of coreclr, once binding symbols up
+//! front and once lazily. It doesn't export the hosting API, so `build`
+//! gives up right after loading it, and getting that far is the test.
+#![cfg(unix)]
+extern crate coreclr;
+
+use coreclr::{ClrError, ClrHostBuilder};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+#[test]
+fn loads_with_symbols_bound_now() {
+    check_loads("now", false);
+}
+
+#[test]
+fn loads_with_symbols_bound_lazily() {
+    check_loads("lazy", true);
+}
+
+fn check_loads(name: &str, lazy: bool) {
+    let library = match system_library() {
+        Some(library) => library,
+        None => {
+            eprintln!("no libm.so.6 found; skipping");
+            return;
+        }
+    };
+
+    // the library's own directory holds no assemblies, so the TPA list
+    // needs one to get past the builder's checks. it's never opened.
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("loader").join(name);
+    fs::create_dir_all(&dir).unwrap();
+    let mut builder = ClrHostBuilder::new();
+    builder.with_coreclr_library_file(&library)
+        .with_trusted_platform_assembly(dir.join("System.Private.CoreLib.dll"));
+    if lazy {
+        builder.with_lazy_binding();
+    }
+
+    match builder.build() {
+        Err(ClrError::LibraryLoad(err)) => panic!("could not load {}: {}", library.display(), err),
+        Err(err) => {
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+            assert!(err.to_string().contains("does not export coreclr_initialize"), "{}", err);
+        }
+        Ok(_) => panic!("{} was taken for coreclr", library.display())
+    }
+}
+
+// libm is on every glibc system, in one of a handful of places.
+fn system_library() -> Option<PathBuf> {
+    let mut dirs = vec![];
+    for root in &["/lib64", "/usr/lib64", "/lib", "/usr/lib"] {
+        dirs.push(PathBuf::from(root));
+        if let Ok(entries) = fs::read_dir(root) {
+            dirs.extend(entries.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.to_string_lossy().ends_with("-linux-gnu")));
+        }
+    }
+
+    dirs.iter().map(|dir| dir.join("libm.so.6")).find(|p| p.is_file())
+}